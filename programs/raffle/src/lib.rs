@@ -2,9 +2,13 @@ use anchor_lang::prelude::*;
 use anchor_lang::solana_program::hash::hash;
 use anchor_lang::system_program;
 use anchor_spl::metadata::MetadataAccount;
-use anchor_spl::metadata::{sign_metadata, SignMetadata};
+use anchor_spl::metadata::{
+    burn_nft, freeze_delegated_account, sign_metadata, thaw_delegated_account, BurnNft,
+    FreezeDelegatedAccount, SignMetadata, ThawDelegatedAccount,
+};
 use anchor_spl::token_interface::{
-    mint_to, transfer_checked, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked,
+    approve, mint_to, transfer_checked, Approve, Mint, MintTo, TokenAccount, TokenInterface,
+    TransferChecked,
 };
 use anchor_spl::{
     associated_token::AssociatedToken,
@@ -28,6 +32,33 @@ pub const NAME: &str = "Token Lottery Ticket #";
 #[constant]
 pub const symbol: &str = "TLT";
 
+#[constant]
+pub const MAX_BENEFICIARIES: u8 = 5;
+
+// shortest delay a queued config change can be given
+#[constant]
+pub const MIN_CONFIG_TIMELOCK: i64 = 60 * 60;
+
+// longest delay a queued config change can be given
+#[constant]
+pub const MAX_CONFIG_TIMELOCK: i64 = 30 * 24 * 60 * 60;
+
+// longest claim window the claim policy can set
+#[constant]
+pub const MAX_CLAIM_WINDOW: i64 = 30 * 24 * 60 * 60;
+
+// seconds a new lottery gives the winner to claim, every draw needs a claim deadline
+#[constant]
+pub const DEFAULT_CLAIM_WINDOW: i64 = 30 * 24 * 60 * 60;
+
+// seconds ticket holders and sponsors of a cancelled round have to take their refund
+#[constant]
+pub const REFUND_WINDOW: i64 = 7 * 24 * 60 * 60;
+
+// fixed capacity of a round's weight index, weighted rounds must cap their tickets at this
+#[constant]
+pub const MAX_WEIGHTED_TICKETS: u64 = 1_000;
+
 #[constant]
 pub const url: &str =
     "https://raw.githubusercontent.com/Emman442/Quiz-application-with-leaderboard-feature/main/mpl.json";
@@ -38,7 +69,15 @@ declare_id!("BQuBEeVWhtjKUSkmGPEoUo5s3zPnukrFQaFE9FTgFCdN");
 pub mod raffle {
     use super::*;
 
-    pub fn buy_ticket(ctx: Context<BuyTicket>) -> Result<()> {
+    pub fn buy_ticket(
+        ctx: Context<BuyTicket>,
+        proof: Vec<[u8; 32]>,
+        allocation: u64,
+    ) -> Result<()> {
+        require!(
+            !ctx.accounts.token_lottery.sales_paused,
+            ErrorCode::LotteryPaused
+        );
         let clock = Clock::get()?;
         let ticket_name = NAME.to_owned()
             + ctx
@@ -48,11 +87,49 @@ pub mod raffle {
                 .to_string()
                 .as_str();
 
-        if clock.unix_timestamp < ctx.accounts.token_lottery.start_time
-            || clock.unix_timestamp > ctx.accounts.token_lottery.end_time
+        let now = ctx.accounts.token_lottery.window_now(&clock);
+        if now < ctx.accounts.token_lottery.start_time || now > ctx.accounts.token_lottery.end_time
         {
             return Err(ErrorCode::LotteryNotOpen.into());
         }
+        require!(
+            !ctx.accounts.token_lottery.cancelled,
+            ErrorCode::LotteryNotOpen
+        );
+
+        // sales close on their own once the round's total cap is reached
+        require!(!ctx.accounts.token_lottery.sold_out(), ErrorCode::SoldOut);
+
+        let max_tickets_per_wallet = ctx.accounts.token_lottery.max_tickets_per_wallet;
+        require!(
+            max_tickets_per_wallet == 0
+                || ctx.accounts.player_entry.tickets < max_tickets_per_wallet,
+            ErrorCode::WalletTicketLimit
+        );
+
+        // allowlisted wallets only until presale_end_time, leaf = hash(payer || allocation)
+        let merkle_root = ctx.accounts.token_lottery.merkle_root;
+        if merkle_root != [0u8; 32] && now < ctx.accounts.token_lottery.presale_end_time {
+            let leaf =
+                hash(&[ctx.accounts.payer.key().as_ref(), &allocation.to_le_bytes()].concat())
+                    .to_bytes();
+            require!(
+                verify_merkle_proof(&proof, merkle_root, leaf),
+                ErrorCode::NotAllowlisted
+            );
+            // 0 = no per-address allocation
+            require!(
+                allocation == 0 || ctx.accounts.player_entry.tickets < allocation,
+                ErrorCode::WalletTicketLimit
+            );
+        }
+
+        check_entry_gate(
+            ctx.accounts.token_lottery.entry_gate,
+            ctx.accounts.payer.key(),
+            ctx.accounts.gate_token_account.as_deref(),
+            ctx.accounts.gate_metadata.as_deref(),
+        )?;
 
         // Transfer tokens to the vault
         let decimals = ctx.accounts.token_mint.decimals;
@@ -71,11 +148,60 @@ pub mod raffle {
             decimals,
         )?;
 
+        // the referrer's share stays in the vault but is credited to them instead of the pot,
+        // per round so the credit can be voided if the round is cancelled
+        let mut pot_share = ctx.accounts.token_lottery.ticket_price;
+        if let Some(referral_accrual) = ctx.accounts.referral_accrual.as_mut() {
+            require_keys_neq!(
+                referral_accrual.referrer,
+                ctx.accounts.payer.key(),
+                ErrorCode::SelfReferral
+            );
+            let round_referral = ctx
+                .accounts
+                .round_referral
+                .as_mut()
+                .ok_or(ErrorCode::InvalidReferralAccounts)?;
+
+            let referral_amount = ctx
+                .accounts
+                .token_lottery
+                .ticket_price
+                .checked_mul(ctx.accounts.token_lottery.referral_bps as u64)
+                .unwrap()
+                .checked_div(10_000)
+                .unwrap();
+            pot_share = pot_share.checked_sub(referral_amount).unwrap();
+
+            round_referral.round_id = ctx.accounts.token_lottery.round_id;
+            round_referral.referrer = referral_accrual.referrer;
+            round_referral.amount = round_referral.amount.checked_add(referral_amount).unwrap();
+            round_referral.bump = ctx.bumps.round_referral.unwrap();
+            referral_accrual.tickets_referred =
+                referral_accrual.tickets_referred.checked_add(1).unwrap();
+
+            emit!(ReferralCredited {
+                round_id: ctx.accounts.token_lottery.round_id,
+                referrer: referral_accrual.referrer,
+                buyer: ctx.accounts.payer.key(),
+                amount: referral_amount,
+                tickets_referred: referral_accrual.tickets_referred,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp
+            });
+        }
+
         ctx.accounts.token_lottery.pot_amount = ctx
             .accounts
             .token_lottery
             .pot_amount
-            .checked_add(ctx.accounts.token_lottery.ticket_price)
+            .checked_add(pot_share)
+            .unwrap();
+        ctx.accounts.token_lottery.ticket_sales = ctx
+            .accounts
+            .token_lottery
+            .ticket_sales
+            .checked_add(pot_share)
             .unwrap();
 
         let round_id_bytes = ctx.accounts.token_lottery.round_id.to_le_bytes();
@@ -171,6 +297,59 @@ pub mod raffle {
             None,
         )?;
 
+        // the master edition takes over the ticket mint's freeze authority, so delegate the
+        // ticket to collection_mint to be able to freeze it once sales close
+        approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.destination.to_account_info(),
+                    delegate: ctx.accounts.collection_mint.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        let player_entry = &mut ctx.accounts.player_entry;
+        player_entry.round_id = ctx.accounts.token_lottery.round_id;
+        player_entry.player = ctx.accounts.payer.key();
+        player_entry.tickets = player_entry.tickets.checked_add(1).unwrap();
+        player_entry.bump = ctx.bumps.player_entry;
+
+        // the winner is drawn from the running total of ticket weights
+        let weight = ticket_weight(
+            &ctx.accounts.token_lottery.weight_bonus,
+            now,
+            ctx.accounts.payer.key(),
+            ctx.accounts.partner_token_account.as_deref(),
+        );
+        // unweighted rounds are drawn uniformly and have no index
+        require!(
+            ctx.accounts.weight_index.is_some()
+                == ctx.accounts.token_lottery.weight_bonus.is_weighted(),
+            ErrorCode::InvalidWeightIndex
+        );
+        if let Some(loader) = &ctx.accounts.weight_index {
+            // the round's first purchase creates the index and gets its rent back on close
+            let created = loader.as_ref().try_borrow_data()?[..8] == [0u8; 8];
+            let weight_index = &mut if created {
+                loader.load_init()?
+            } else {
+                loader.load_mut()?
+            };
+            if created {
+                weight_index.round_id = ctx.accounts.token_lottery.round_id;
+                weight_index.payer = ctx.accounts.payer.key();
+                weight_index.bump = ctx.bumps.weight_index.unwrap();
+            }
+            require!(weight_index.len < MAX_WEIGHTED_TICKETS, ErrorCode::SoldOut);
+            weight_index.total_weight = weight_index.total_weight.checked_add(weight).unwrap();
+            let len = weight_index.len as usize;
+            weight_index.cumulative[len] = weight_index.total_weight;
+            weight_index.len += 1;
+        }
+
         // increment the ticket counter (this increases ticket index for next mint)
         ctx.accounts.token_lottery.total_tickets = ctx
             .accounts
@@ -181,7 +360,10 @@ pub mod raffle {
 
         emit!(BoughtTicket {
             price: ctx.accounts.token_lottery.ticket_price,
-            current_total_tickets: ctx.accounts.token_lottery.total_tickets
+            current_total_tickets: ctx.accounts.token_lottery.total_tickets,
+            weight,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
         });
 
         Ok(())
@@ -193,17 +375,17 @@ pub mod raffle {
         new_end_time: i64,
         new_ticket_price: u64,
     ) -> Result<()> {
-        let lottery = &mut ctx.accounts.token_lottery;
-        lottery.start_time = new_start_time;
-        lottery.end_time = new_end_time;
-        lottery.ticket_price = new_ticket_price;
-        lottery.total_tickets = 0;
-        lottery.winner_chosen = false;
-        lottery.winner = 0;
-        lottery.pot_amount = 0;
-        // bump round id to create fresh PDAs for next initialize_lottery
-        lottery.round_id = lottery.round_id.checked_add(1).unwrap();
-        Ok(())
+        if ctx.accounts.operator.key() != ctx.accounts.token_lottery.operator {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+
+        open_next_round(
+            &mut ctx.accounts.token_lottery,
+            &mut ctx.accounts.round_history,
+            new_start_time,
+            new_end_time,
+            new_ticket_price,
+        )
     }
 
     pub fn claim_winnings(ctx: Context<ClaimWinnings>) -> Result<()> {
@@ -212,57 +394,47 @@ pub mod raffle {
             "Winner chosen: {}",
             ctx.accounts.token_lottery.winner_chosen
         );
-        require!(
-            ctx.accounts.token_lottery.winner_chosen,
-            ErrorCode::WinnerNotChosen
-        );
-
-        // Check if token is a part of the collection
-        require!(
-            ctx.accounts.metadata.collection.as_ref().unwrap().verified,
-            ErrorCode::NotVerifiedTicket
-        );
-        require!(
-            ctx.accounts.metadata.collection.as_ref().unwrap().key
-                == ctx.accounts.collection_mint.key(),
-            ErrorCode::IncorrectTicket
-        );
-
-        let ticket_name = NAME.to_owned() + &ctx.accounts.token_lottery.winner.to_string();
-        let metadata_name = ctx.accounts.metadata.name.replace("\u{0}", "");
-
-        msg!("Ticket name: {}", ticket_name);
-        msg!("Metdata name: {}", metadata_name);
-
-        // Check if the winner has the winning ticket
-        require!(metadata_name == ticket_name, ErrorCode::IncorrectTicket);
-        require!(
-            ctx.accounts.destination.amount > 0,
-            ErrorCode::IncorrectTicket
-        );
-
-        // token_lottery is signer authority for reward_vault
-        let seeds = &[b"token_lottery".as_ref(), &[ctx.bumps.token_lottery]];
-        let signer = &[&seeds[..]];
+        let clock = Clock::get()?;
+        check_winnings_claimable(
+            &ctx.accounts.token_lottery,
+            &ctx.accounts.round_history,
+            clock.unix_timestamp,
+        )?;
 
-        let decimals = ctx.accounts.reward_mint.decimals;
+        let ticket_name = verify_winning_ticket(
+            &ctx.accounts.token_lottery,
+            &ctx.accounts.metadata,
+            ctx.accounts.collection_mint.key(),
+            &ctx.accounts.destination,
+        )?;
 
-        let cpi_accounts = TransferChecked {
-            from: ctx.accounts.reward_vault.to_account_info(),
-            to: ctx.accounts.winner_token_account.to_account_info(),
-            mint: ctx.accounts.reward_mint.to_account_info(),
-            authority: ctx.accounts.token_lottery.to_account_info(),
+        let payout = WinningsPayout {
+            token_lottery: ctx.accounts.token_lottery.to_account_info(),
+            reward_mint: ctx.accounts.reward_mint.to_account_info(),
+            reward_vault: ctx.accounts.reward_vault.to_account_info(),
+            recipient_token_account: ctx.accounts.winner_token_account.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            decimals: ctx.accounts.reward_mint.decimals,
         };
-
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-
-        transfer_checked(cpi_ctx, ctx.accounts.token_lottery.pot_amount, decimals)?;
-        ctx.accounts.token_lottery.pot_amount = 0;
+        let amount = pay_winnings(
+            payout,
+            &mut ctx.accounts.token_lottery,
+            &mut ctx.accounts.round_history,
+            ctx.accounts.vesting_record.as_deref_mut(),
+            ctx.bumps.vesting_record,
+            ctx.accounts.payer.key(),
+            clock.unix_timestamp,
+        )?;
 
         emit!(WinningsClaimed {
-            ticket_name: ticket_name,
-            destination_account: ctx.accounts.destination.key()
+            round_id: ctx.accounts.round_history.round_id,
+            ticket_name,
+            ticket_mint: ctx.accounts.ticket_mint.key(),
+            destination_account: ctx.accounts.destination.key(),
+            claimant: ctx.accounts.payer.key(),
+            caller: ctx.accounts.payer.key(),
+            recipient_token_account: ctx.accounts.winner_token_account.key(),
+            amount
         });
 
         Ok(())
@@ -271,15 +443,37 @@ pub mod raffle {
     pub fn commit_winner(ctx: Context<CommitWinner>, client_seed: u8) -> Result<()> {
         let clock = Clock::get()?;
         let token_lottery = &mut ctx.accounts.token_lottery;
-        if ctx.accounts.payer.key() != token_lottery.authority {
+        if ctx.accounts.payer.key() != token_lottery.operator {
             return Err(ErrorCode::NotAuthorized.into());
         }
 
         require!(
-            clock.unix_timestamp >= token_lottery.end_time,
+            token_lottery.sales_closed(token_lottery.window_now(&clock)),
             ErrorCode::LotteryNotCompleted
         );
         require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+        require!(!token_lottery.cancelled, ErrorCode::RoundCancelled);
+        // nothing to draw, restart_lottery rolls the pot over instead
+        require!(token_lottery.total_tickets > 0, ErrorCode::NoTicketsSold);
+        // undersubscribed rounds go to enter_refund_mode instead
+        require!(
+            token_lottery.meets_min_participation(),
+            ErrorCode::BelowMinimumParticipation
+        );
+        require!(
+            ctx.accounts.weight_index.is_some() || !token_lottery.weight_bonus.is_weighted(),
+            ErrorCode::InvalidWeightIndex
+        );
+        // no ticket may change hands once the randomness is requested
+        require!(
+            ctx.accounts.round_history.freeze_progress == token_lottery.total_tickets,
+            ErrorCode::TicketsNotFrozen
+        );
+        // an unclaimed pot with no deadline could never be swept or rolled over
+        require!(
+            token_lottery.claim_window > 0,
+            ErrorCode::InvalidClaimWindow
+        );
 
         let ix = create_request_randomness_ix(RequestRandomnessParams {
             payer: ctx.accounts.payer.key(),
@@ -287,19 +481,39 @@ pub mod raffle {
             callback_program_id: ID,
             callback_discriminator: instruction::CallbackChooseWinner::DISCRIMINATOR.to_vec(),
             caller_seed: [client_seed; 32],
-            // specify token_lottery for callback
-            accounts_metas: Some(vec![SerializableAccountMeta {
-                pubkey: ctx.accounts.token_lottery.key(),
-                is_signer: false,
-                is_writable: true,
-            }]),
+            // specify token_lottery and the round's history for callback
+            accounts_metas: Some(vec![
+                SerializableAccountMeta {
+                    pubkey: ctx.accounts.token_lottery.key(),
+                    is_signer: false,
+                    is_writable: true,
+                },
+                SerializableAccountMeta {
+                    pubkey: ctx.accounts.round_history.key(),
+                    is_signer: false,
+                    is_writable: true,
+                },
+                // the program id stands in for the index of an unweighted round
+                SerializableAccountMeta {
+                    pubkey: ctx
+                        .accounts
+                        .weight_index
+                        .as_ref()
+                        .map_or(ID, |weight_index| weight_index.key()),
+                    is_signer: false,
+                    is_writable: false,
+                },
+            ]),
             ..Default::default()
         });
         ctx.accounts
             .invoke_signed_vrf(&ctx.accounts.payer.to_account_info(), &ix)?;
+        ctx.accounts.token_lottery.draw_requested = true;
 
         emit!(WinnerCommited {
-            oracle_queue: ctx.accounts.oracle_queue.key()
+            oracle_queue: ctx.accounts.oracle_queue.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
         });
         Ok(())
     }
@@ -322,18 +536,52 @@ pub mod raffle {
             token_lottery.total_tickets > 0,
             ErrorCode::LotteryNotCompleted
         );
+        require!(!token_lottery.cancelled, ErrorCode::RoundCancelled);
 
-        let random_number = ephemeral_vrf_sdk::rnd::random_u8_with_range(
-            &randomness,
-            0,
-            token_lottery.total_tickets as u8 - 1,
-        );
-        let winner_index = random_number as u64;
+        // 128 bits of randomness keeps the modulo bias negligible for any u64 total
+        let random_number = u128::from_le_bytes(randomness[..16].try_into().unwrap());
+        let (winner_index, total_weight) = if token_lottery.weight_bonus.is_weighted() {
+            let weight_index = ctx
+                .accounts
+                .weight_index
+                .as_ref()
+                .ok_or(ErrorCode::InvalidWeightIndex)?
+                .load()?;
+            require!(
+                weight_index.len == token_lottery.total_tickets && weight_index.total_weight > 0,
+                ErrorCode::InvalidWeightIndex
+            );
+            let cumulative = &weight_index.cumulative[..weight_index.len as usize];
+            (
+                pick_weighted_winner(cumulative, random_number),
+                weight_index.total_weight,
+            )
+        } else {
+            (
+                (random_number % token_lottery.total_tickets as u128) as u64,
+                token_lottery.total_tickets,
+            )
+        };
         token_lottery.winner = winner_index;
         token_lottery.winner_chosen = true;
+        token_lottery.claim_deadline = clock
+            .unix_timestamp
+            .checked_add(token_lottery.claim_window)
+            .ok_or(ErrorCode::InvalidClaimWindow)?;
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.winner = winner_index;
+        round_history.winner_chosen = true;
+        round_history.total_tickets = token_lottery.total_tickets;
+        round_history.pot_amount = token_lottery.pot_amount;
+        round_history.ticket_price = token_lottery.ticket_price;
+        round_history.total_weight = total_weight;
+
         emit!(SelectWinner {
             winner: ctx.accounts.token_lottery.winner,
-            winner_chosen: ctx.accounts.token_lottery.winner_chosen
+            winner_chosen: ctx.accounts.token_lottery.winner_chosen,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
         });
 
         Ok(())
@@ -345,487 +593,4238 @@ pub mod raffle {
         end_time: i64,
         price: u64,
     ) -> Result<()> {
+        let clock = Clock::get()?;
+        validate_round_params(start_time, end_time, price, clock.unix_timestamp)?;
+
         ctx.accounts.token_lottery.bump = ctx.bumps.token_lottery;
         ctx.accounts.token_lottery.start_time = start_time;
         ctx.accounts.token_lottery.end_time = end_time;
         ctx.accounts.token_lottery.ticket_price = price;
         ctx.accounts.token_lottery.authority = ctx.accounts.signer.key();
+        ctx.accounts.token_lottery.token_mint = ctx.accounts.token_mint.key();
         ctx.accounts.token_lottery.pot_amount = 0;
         ctx.accounts.token_lottery.winner_chosen = false;
         // initial round id 0
         ctx.accounts.token_lottery.round_id = 0;
         ctx.accounts.token_lottery.total_tickets = 0;
         ctx.accounts.token_lottery.winner = 0;
+        ctx.accounts.token_lottery.max_tickets_per_wallet = 0;
+        ctx.accounts.token_lottery.max_total_tickets = 0;
+        ctx.accounts.token_lottery.merkle_root = [0u8; 32];
+        ctx.accounts.token_lottery.presale_end_time = 0;
+        ctx.accounts.token_lottery.entry_gate = EntryGate::None;
+        ctx.accounts.token_lottery.referral_bps = 0;
+        ctx.accounts.token_lottery.sponsored_amount = 0;
+        ctx.accounts.token_lottery.ticket_sales = 0;
+        ctx.accounts.token_lottery.cancelled = false;
+        ctx.accounts.token_lottery.claim_window = DEFAULT_CLAIM_WINDOW;
+        ctx.accounts.token_lottery.claim_deadline = 0;
+        ctx.accounts.token_lottery.unclaimed_policy = UnclaimedPolicy::Rollover;
+        ctx.accounts.token_lottery.treasury = ctx.accounts.signer.key();
+        ctx.accounts.token_lottery.charity = Pubkey::default();
+        ctx.accounts.token_lottery.vesting = None;
+        ctx.accounts.token_lottery.beneficiaries = Vec::new();
+        ctx.accounts.token_lottery.beneficiaries_paid = false;
+        ctx.accounts.token_lottery.pending_authority = None;
+        ctx.accounts.token_lottery.operator = ctx.accounts.signer.key();
+        ctx.accounts.token_lottery.treasurer = ctx.accounts.signer.key();
+        ctx.accounts.token_lottery.pauser = ctx.accounts.signer.key();
+        ctx.accounts.token_lottery.sales_paused = false;
+        ctx.accounts.token_lottery.claims_paused = false;
+        ctx.accounts.token_lottery.claims_paused_at = 0;
+        ctx.accounts.token_lottery.draw_requested = false;
+        ctx.accounts.token_lottery.oracle_queue = ephemeral_vrf_sdk::consts::DEFAULT_QUEUE;
+        ctx.accounts.token_lottery.config_timelock = MIN_CONFIG_TIMELOCK;
+        ctx.accounts.token_lottery.schedule = None;
+        ctx.accounts.token_lottery.min_tickets = 0;
+        ctx.accounts.token_lottery.min_pot = 0;
+        ctx.accounts.token_lottery.window_mode = WindowMode::Timestamp;
+        ctx.accounts.token_lottery.weight_bonus = WeightBonus::default();
 
         emit!(InitializedConfig {
             start_time: start_time,
             end_time: end_time,
             price: price,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
         Ok(())
     }
 
     pub fn initialize_lottery(ctx: Context<InitializeLottery>) -> Result<()> {
-        // Store round_id bytes in a variable so they live long enough
-        let round_id_bytes = ctx.accounts.token_lottery.round_id.to_le_bytes();
+        if ctx.accounts.payer.key() != ctx.accounts.token_lottery.operator {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+
+        create_round_collection(
+            ctx.accounts.round_collection(),
+            ctx.accounts.token_lottery.round_id,
+            ctx.bumps.collection_mint,
+        )?;
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.round_id = ctx.accounts.token_lottery.round_id;
+        round_history.collection_mint = ctx.accounts.collection_mint.key();
+        round_history.bump = ctx.bumps.round_history;
+
+        emit!(InitializedLottery {
+            collection_mint: ctx.accounts.collection_mint.key()
+        });
+        Ok(())
+    }
+
+    // remaining accounts: [ticket_mint, ticket_token_account, ticket_master_edition] per ticket,
+    // in ticket index order starting at round_history.freeze_progress, with the token account
+    // that holds the ticket. commit_winner waits until every ticket sold is frozen or burned.
+    // Tickets stay tradable during the sale, so a holder can revoke the collection_mint delegate
+    // (or move the ticket to a new account) before this runs. Such a ticket can't be frozen and
+    // holds up the draw until it is delegated to collection_mint again or the round is cancelled.
+    pub fn freeze_tickets<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FreezeTickets<'info>>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &ctx.accounts.token_lottery;
+
+        require!(
+            token_lottery.sales_closed(token_lottery.window_now(&clock)),
+            ErrorCode::LotteryNotCompleted
+        );
+        require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+        require!(
+            ctx.remaining_accounts.len() % 3 == 0,
+            ErrorCode::InvalidTicketAccounts
+        );
 
-        // signer seeds for collection_mint PDA
+        let round_id_bytes = token_lottery.round_id.to_le_bytes();
         let signer_seeds: &[&[&[u8]]] = &[&[
             b"collection_mint".as_ref(),
             round_id_bytes.as_ref(),
             &[ctx.bumps.collection_mint],
         ]];
-        msg!("Creating Mint Account");
-        // Mint 1 token of the collection (collection supply/marker)
-        mint_to(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                MintTo {
-                    mint: ctx.accounts.collection_mint.to_account_info(),
-                    to: ctx.accounts.collection_token_account.to_account_info(),
-                    authority: ctx.accounts.collection_mint.to_account_info(),
-                },
-                &signer_seeds,
-            ),
-            1,
-        )?;
 
-        msg!("Creating Metadata Account v3");
-        create_metadata_accounts_v3(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                CreateMetadataAccountsV3 {
-                    metadata: ctx.accounts.metadata.to_account_info(),
-                    mint: ctx.accounts.collection_mint.to_account_info(),
-                    mint_authority: ctx.accounts.collection_mint.to_account_info(),
-                    payer: ctx.accounts.payer.to_account_info(),
-                    update_authority: ctx.accounts.collection_mint.to_account_info(),
-                    system_program: ctx.accounts.system_program.to_account_info(),
-                    rent: ctx.accounts.rent.to_account_info(),
+        let mut ticket_index = ctx.accounts.round_history.freeze_progress;
+        let mut frozen: u64 = 0;
+        for ticket in ctx.remaining_accounts.chunks(3) {
+            let (ticket_mint, ticket_account, ticket_edition) =
+                (&ticket[0], &ticket[1], &ticket[2]);
+            require!(
+                ticket_index < token_lottery.total_tickets,
+                ErrorCode::InvalidTicketAccounts
+            );
+            let (expected_mint, _) = Pubkey::find_program_address(
+                &[round_id_bytes.as_ref(), ticket_index.to_le_bytes().as_ref()],
+                &ID,
+            );
+            require_keys_eq!(
+                ticket_mint.key(),
+                expected_mint,
+                ErrorCode::InvalidTicketAccounts
+            );
+            ticket_index = ticket_index.checked_add(1).unwrap();
+
+            // a burned ticket can't move any more
+            if InterfaceAccount::<Mint>::try_from(ticket_mint)?.supply == 0 {
+                continue;
+            }
+            let token_account = InterfaceAccount::<TokenAccount>::try_from(ticket_account)?;
+            require!(
+                token_account.mint == ticket_mint.key() && token_account.amount == 1,
+                ErrorCode::InvalidTicketAccounts
+            );
+            require!(
+                Option::<Pubkey>::from(token_account.delegate)
+                    == Some(ctx.accounts.collection_mint.key()),
+                ErrorCode::TicketNotFreezable
+            );
+
+            freeze_delegated_account(CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                FreezeDelegatedAccount {
+                    // not read by the metadata program, the edition is enough
+                    metadata: ticket_edition.clone(),
+                    delegate: ctx.accounts.collection_mint.to_account_info(),
+                    token_account: ticket_account.clone(),
+                    edition: ticket_edition.clone(),
+                    mint: ticket_mint.clone(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
                 },
-                &signer_seeds,
-            ),
-            DataV2 {
-                name: NAME.to_string(),
-                symbol: symbol.to_string(),
-                uri: url.to_string(),
-                seller_fee_basis_points: 0,
-                creators: Some(vec![Creator {
-                    address: ctx.accounts.collection_mint.key(),
-                    verified: false,
-                    share: 100,
-                }]),
-                collection: None,
-                uses: None,
-            },
-            true,
-            true,
-            Some(CollectionDetails::V1 { size: 0 }),
+                signer_seeds,
+            ))?;
+            frozen = frozen.checked_add(1).unwrap();
+        }
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.frozen_tickets = round_history.frozen_tickets.checked_add(frozen).unwrap();
+        round_history.freeze_progress = ticket_index;
+
+        emit!(TicketsFrozen {
+            round_id: round_history.round_id,
+            frozen,
+            freeze_progress: ticket_index,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // remaining accounts: [ticket_mint, ticket_token_account, ticket_master_edition] per ticket.
+    // Takes the round id so tickets of a closed round, the winning one included, can be thawed.
+    pub fn thaw_tickets<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ThawTickets<'info>>,
+        round_id: u64,
+    ) -> Result<()> {
+        let round_history = &ctx.accounts.round_history;
+
+        require!(
+            round_history.winner_chosen || round_history.cancelled,
+            ErrorCode::WinnerNotChosen
+        );
+        require!(
+            ctx.remaining_accounts.len() % 3 == 0,
+            ErrorCode::InvalidTicketAccounts
         );
 
-        msg!("Creating Master Edition Account");
-        create_master_edition_v3(
-            CpiContext::new_with_signer(
+        let round_id_bytes = round_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"collection_mint".as_ref(),
+            round_id_bytes.as_ref(),
+            &[ctx.bumps.collection_mint],
+        ]];
+
+        let mut thawed: u64 = 0;
+        for ticket in ctx.remaining_accounts.chunks(3) {
+            let (ticket_mint, ticket_account, ticket_edition) =
+                (&ticket[0], &ticket[1], &ticket[2]);
+            let token_account = InterfaceAccount::<TokenAccount>::try_from(ticket_account)?;
+            require_keys_eq!(
+                token_account.mint,
+                ticket_mint.key(),
+                ErrorCode::InvalidTicketAccounts
+            );
+
+            if !token_account.is_frozen() {
+                continue;
+            }
+
+            thaw_delegated_account(CpiContext::new_with_signer(
                 ctx.accounts.token_metadata_program.to_account_info(),
-                CreateMasterEditionV3 {
-                    edition: ctx.accounts.master_edition.to_account_info(),
-                    mint: ctx.accounts.collection_mint.to_account_info(),
-                    update_authority: ctx.accounts.collection_mint.to_account_info(),
-                    mint_authority: ctx.accounts.collection_mint.to_account_info(),
-                    payer: ctx.accounts.payer.to_account_info(),
-                    metadata: ctx.accounts.metadata.to_account_info(),
+                ThawDelegatedAccount {
+                    // not read by the metadata program, the edition is enough
+                    metadata: ticket_edition.clone(),
+                    delegate: ctx.accounts.collection_mint.to_account_info(),
+                    token_account: ticket_account.clone(),
+                    edition: ticket_edition.clone(),
+                    mint: ticket_mint.clone(),
                     token_program: ctx.accounts.token_program.to_account_info(),
-                    system_program: ctx.accounts.system_program.to_account_info(),
-                    rent: ctx.accounts.rent.to_account_info(),
                 },
-                &signer_seeds,
-            ),
-            Some(0),
+                signer_seeds,
+            ))?;
+            thawed = thawed.checked_add(1).unwrap();
+        }
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.frozen_tickets = round_history.frozen_tickets.checked_sub(thawed).unwrap();
+
+        let clock = Clock::get()?;
+        emit!(TicketsThawed {
+            round_id,
+            thawed,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn burn_ticket(ctx: Context<BurnTicket>, round_id: u64, ticket_index: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
         );
+        let round_history = &mut ctx.accounts.round_history;
+        if round_history.cancelled {
+            // a cancelled round's ticket can go once it's refunded or can no longer be
+            require!(
+                ctx.accounts.ticket_refund.is_some()
+                    || clock.unix_timestamp > round_history.refund_deadline,
+                ErrorCode::RefundWindowOpen
+            );
+        } else {
+            require!(round_history.winner_chosen, ErrorCode::WinnerNotChosen);
+            require!(
+                ticket_index != round_history.winner,
+                ErrorCode::WinningTicket
+            );
+        }
 
-        msg!("Verifying Collection...");
-        sign_metadata(CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            SignMetadata {
-                creator: ctx.accounts.collection_mint.to_account_info(),
-                metadata: ctx.accounts.metadata.to_account_info(),
-            },
-            &signer_seeds,
-        ));
+        // a ticket frozen for the draw has to be thawed before it can be burned
+        if ctx.accounts.ticket_token_account.is_frozen() {
+            let round_id_bytes = round_id.to_le_bytes();
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                b"collection_mint".as_ref(),
+                round_id_bytes.as_ref(),
+                &[ctx.bumps.collection_mint],
+            ]];
+            thaw_delegated_account(CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                ThawDelegatedAccount {
+                    metadata: ctx.accounts.ticket_metadata.to_account_info(),
+                    delegate: ctx.accounts.collection_mint.to_account_info(),
+                    token_account: ctx.accounts.ticket_token_account.to_account_info(),
+                    edition: ctx.accounts.ticket_master_edition.to_account_info(),
+                    mint: ctx.accounts.ticket_mint.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+                signer_seeds,
+            ))?;
+            round_history.frozen_tickets = round_history.frozen_tickets.checked_sub(1).unwrap();
+        }
 
-        emit!(InitializedLottery {
-            collection_mint: ctx.accounts.collection_mint.key()
+        // closes the token, metadata and edition accounts, rent goes back to the holder
+        burn_nft(
+            CpiContext::new(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                BurnNft {
+                    metadata: ctx.accounts.ticket_metadata.to_account_info(),
+                    owner: ctx.accounts.payer.to_account_info(),
+                    mint: ctx.accounts.ticket_mint.to_account_info(),
+                    token: ctx.accounts.ticket_token_account.to_account_info(),
+                    edition: ctx.accounts.ticket_master_edition.to_account_info(),
+                    spl_token: ctx.accounts.token_program.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(vec![ctx.accounts.collection_metadata.to_account_info()]),
+            Some(ctx.accounts.collection_metadata.key()),
+        )?;
+
+        emit!(TicketBurned {
+            round_id,
+            ticket_index,
+            owner: ctx.accounts.payer.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
         });
         Ok(())
     }
-}
 
-// ---------------------------- Accounts ---------------------------- //
+    pub fn set_ticket_caps(
+        ctx: Context<SetTicketCaps>,
+        max_tickets_per_wallet: u64,
+        max_total_tickets: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        require!(
+            !token_lottery.draw_requested,
+            ErrorCode::ConfigChangeNotAllowed
+        );
 
-#[derive(Accounts)]
-pub struct InitializeLottery<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        // once the round is open caps can only be raised
+        if token_lottery.window_now(&Clock::get()?) >= token_lottery.start_time {
+            validate_cap_change(
+                token_lottery.max_tickets_per_wallet,
+                max_tickets_per_wallet,
+                0,
+            )?;
+            validate_cap_change(
+                token_lottery.max_total_tickets,
+                max_total_tickets,
+                token_lottery.total_tickets,
+            )?;
+        }
 
-    // collection_mint is now round-scoped (seed includes token_lottery.round_id)
-    #[account(
-        init,
-        payer = payer,
-        mint::decimals = 0,
-        mint::authority = collection_mint,
-        mint::freeze_authority = collection_mint,
-        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub collection_mint: InterfaceAccount<'info, Mint>,
+        // 0 leaves the corresponding cap off
+        token_lottery.max_tickets_per_wallet = max_tickets_per_wallet;
+        token_lottery.max_total_tickets = max_total_tickets;
+        require!(
+            token_lottery.weighted_cap_ok(),
+            ErrorCode::WeightedTicketCap
+        );
 
-    // collection_token_account is the ATA for collection_mint owned by collection_mint PDA
-    #[account(
-        init_if_needed,
-        payer = payer,
-        associated_token::mint = collection_mint,
-        associated_token::authority = collection_mint,
-    )]
-    pub collection_token_account: InterfaceAccount<'info, TokenAccount>,
+        emit!(TicketCapsUpdated {
+            max_tickets_per_wallet,
+            max_total_tickets,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn set_presale(
+        ctx: Context<SetPresale>,
+        merkle_root: [u8; 32],
+        presale_end_time: i64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        // the allowlist can't change under wallets that already bought in
+        require!(
+            token_lottery.total_tickets == 0 && !token_lottery.draw_requested,
+            ErrorCode::ConfigChangeNotAllowed
+        );
+        // the presale has to end inside the round window, in the round's window unit
+        if merkle_root != [0u8; 32] {
+            require!(
+                presale_end_time > token_lottery.start_time
+                    && presale_end_time <= token_lottery.end_time,
+                ErrorCode::InvalidRoundWindow
+            );
+        }
+
+        // an all-zero root turns the presale off
+        token_lottery.merkle_root = merkle_root;
+        token_lottery.presale_end_time = presale_end_time;
+
+        emit!(PresaleUpdated {
+            merkle_root,
+            presale_end_time,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn set_entry_gate(ctx: Context<SetEntryGate>, entry_gate: EntryGate) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+
+        token_lottery.entry_gate = entry_gate;
+
+        emit!(EntryGateUpdated {
+            entry_gate,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let clock = Clock::get()?;
+        let referral_accrual = &mut ctx.accounts.referral_accrual;
+        referral_accrual.referrer = ctx.accounts.referrer.key();
+        referral_accrual.bump = ctx.bumps.referral_accrual;
+
+        emit!(ReferrerRegistered {
+            referrer: ctx.accounts.referrer.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // credits are paid once the round is drawn, a cancelled round refunds them to its players
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>, round_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let round_history = &ctx.accounts.round_history;
+        require!(!round_history.cancelled, ErrorCode::RoundCancelled);
+        require!(round_history.winner_chosen, ErrorCode::WinnerNotChosen);
+        let amount = ctx.accounts.round_referral.amount;
+        require!(amount > 0, ErrorCode::NothingToClaim);
+
+        // token_lottery is signer authority for the vault
+        let seeds = &[
+            b"token_lottery".as_ref(),
+            &[ctx.accounts.token_lottery.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.raffle_vault_account.to_account_info(),
+                    to: ctx.accounts.referrer_token_account.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.token_lottery.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.token_mint.decimals,
+        )?;
+
+        ctx.accounts.round_referral.amount = 0;
+        let referral_accrual = &mut ctx.accounts.referral_accrual;
+        referral_accrual.claimed = referral_accrual.claimed.checked_add(amount).unwrap();
+
+        emit!(ReferralRewardsClaimed {
+            round_id,
+            referrer: ctx.accounts.referrer.key(),
+            amount,
+            total_claimed: referral_accrual.claimed,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn fund_pot(ctx: Context<FundPot>, amount: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.sales_paused,
+            ErrorCode::LotteryPaused
+        );
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            !ctx.accounts.token_lottery.winner_chosen,
+            ErrorCode::WinnerChosen
+        );
+        require!(
+            !ctx.accounts.token_lottery.cancelled,
+            ErrorCode::RoundCancelled
+        );
+
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.sponsor_token_account.to_account_info(),
+                    to: ctx.accounts.raffle_vault_account.to_account_info(),
+                    authority: ctx.accounts.sponsor.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.token_mint.decimals,
+        )?;
+
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        token_lottery.pot_amount = token_lottery.pot_amount.checked_add(amount).unwrap();
+        token_lottery.sponsored_amount =
+            token_lottery.sponsored_amount.checked_add(amount).unwrap();
+
+        let sponsor_contribution = &mut ctx.accounts.sponsor_contribution;
+        sponsor_contribution.round_id = token_lottery.round_id;
+        sponsor_contribution.sponsor = ctx.accounts.sponsor.key();
+        sponsor_contribution.amount = sponsor_contribution.amount.checked_add(amount).unwrap();
+        sponsor_contribution.bump = ctx.bumps.sponsor_contribution;
+
+        emit!(PotFunded {
+            round_id: token_lottery.round_id,
+            sponsor: ctx.accounts.sponsor.key(),
+            amount,
+            pot_amount: token_lottery.pot_amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn cancel_round(ctx: Context<CancelRound>) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+        require!(!token_lottery.cancelled, ErrorCode::RoundCancelled);
+        // the pending vrf callback would still draw a winner for the refunded round
+        require!(!token_lottery.draw_requested, ErrorCode::DrawRequested);
+
+        reserve_refunds(
+            token_lottery,
+            &mut ctx.accounts.round_history,
+            clock.unix_timestamp,
+        );
+
+        emit!(RoundCancelled {
+            round_id: token_lottery.round_id,
+            pot_amount: token_lottery.pot_amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn set_min_participation(
+        ctx: Context<SetMinParticipation>,
+        min_tickets: u64,
+        min_pot: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        // raising the bar mid-sale could force a refund on players who already entered
+        require!(
+            token_lottery.total_tickets == 0,
+            ErrorCode::ConfigChangeNotAllowed
+        );
+
+        // 0 leaves the corresponding threshold off
+        token_lottery.min_tickets = min_tickets;
+        token_lottery.min_pot = min_pot;
+
+        emit!(MinParticipationUpdated {
+            min_tickets,
+            min_pot,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // permissionless, cancels a round that closed below min_tickets or min_pot so
+    // ticket holders can use refund_ticket
+    pub fn enter_refund_mode(ctx: Context<EnterRefundMode>) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        require!(
+            token_lottery.window_now(&clock) >= token_lottery.end_time,
+            ErrorCode::LotteryNotCompleted
+        );
+        require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+        require!(!token_lottery.cancelled, ErrorCode::RoundCancelled);
+        require!(!token_lottery.draw_requested, ErrorCode::DrawRequested);
+        require!(
+            !token_lottery.meets_min_participation(),
+            ErrorCode::MinimumParticipationMet
+        );
+
+        reserve_refunds(
+            token_lottery,
+            &mut ctx.accounts.round_history,
+            clock.unix_timestamp,
+        );
+
+        emit!(RefundModeEntered {
+            round_id: token_lottery.round_id,
+            total_tickets: token_lottery.total_tickets,
+            pot_amount: token_lottery.pot_amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // holders of a cancelled round's tickets get refund_amount back, once per ticket, until
+    // the round's refund_deadline
+    pub fn refund_ticket(
+        ctx: Context<RefundTicket>,
+        round_id: u64,
+        ticket_index: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let round_history = &ctx.accounts.round_history;
+        require!(round_history.cancelled, ErrorCode::RoundNotCancelled);
+        require!(
+            clock.unix_timestamp <= round_history.refund_deadline,
+            ErrorCode::RefundWindowClosed
+        );
+        require!(
+            ctx.accounts.ticket_token_account.amount > 0,
+            ErrorCode::IncorrectTicket
+        );
+        let amount = round_history.refund_amount;
+        require!(amount > 0, ErrorCode::NothingToClaim);
+
+        let seeds = &[
+            b"token_lottery".as_ref(),
+            &[ctx.accounts.token_lottery.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.raffle_vault_account.to_account_info(),
+                    to: ctx.accounts.holder_token_account.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.token_lottery.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.token_mint.decimals,
+        )?;
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.refund_pool = round_history.refund_pool.checked_sub(amount).unwrap();
+
+        let ticket_refund = &mut ctx.accounts.ticket_refund;
+        ticket_refund.round_id = round_id;
+        ticket_refund.ticket_index = ticket_index;
+        ticket_refund.holder = ctx.accounts.holder.key();
+        ticket_refund.amount = amount;
+        ticket_refund.bump = ctx.bumps.ticket_refund;
+
+        emit!(TicketRefunded {
+            round_id,
+            ticket_index,
+            holder: ctx.accounts.holder.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // sponsors of a cancelled round get their contribution back until the round's
+    // refund_deadline
+    pub fn refund_sponsor(ctx: Context<RefundSponsor>, round_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let round_history = &ctx.accounts.round_history;
+        require!(round_history.cancelled, ErrorCode::RoundNotCancelled);
+        require!(
+            clock.unix_timestamp <= round_history.refund_deadline,
+            ErrorCode::RefundWindowClosed
+        );
+        let amount = ctx.accounts.sponsor_contribution.amount;
+        require!(amount > 0, ErrorCode::NothingToClaim);
+
+        // token_lottery is signer authority for the vault
+        let seeds = &[
+            b"token_lottery".as_ref(),
+            &[ctx.accounts.token_lottery.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.raffle_vault_account.to_account_info(),
+                    to: ctx.accounts.sponsor_token_account.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.token_lottery.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.token_mint.decimals,
+        )?;
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.refund_pool = round_history.refund_pool.checked_sub(amount).unwrap();
+        ctx.accounts.sponsor_contribution.amount = 0;
+
+        emit!(SponsorRefunded {
+            round_id,
+            sponsor: ctx.accounts.sponsor.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // permissionless, once the refund window is over whatever nobody reclaimed joins the
+    // current round's pot
+    pub fn close_refunds(ctx: Context<CloseRefunds>, round_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let round_history = &mut ctx.accounts.round_history;
+        require!(round_history.cancelled, ErrorCode::RoundNotCancelled);
+        require!(
+            clock.unix_timestamp > round_history.refund_deadline,
+            ErrorCode::RefundWindowOpen
+        );
+        let amount = round_history.refund_pool;
+        require!(amount > 0, ErrorCode::NothingToClaim);
+
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        // a drawn pot is fixed, the leftovers wait for the next round
+        require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+        token_lottery.pot_amount = token_lottery.pot_amount.checked_add(amount).unwrap();
+        round_history.refund_pool = 0;
+
+        emit!(RefundsClosed {
+            round_id,
+            to_round_id: token_lottery.round_id,
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn sweep_unclaimed(ctx: Context<SweepUnclaimed>) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &ctx.accounts.token_lottery;
+        if ctx.accounts.treasurer.key() != token_lottery.treasurer {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        require!(!token_lottery.claims_paused, ErrorCode::LotteryPaused);
+        require!(token_lottery.winner_chosen, ErrorCode::WinnerNotChosen);
+        require!(
+            token_lottery.claim_deadline_passed(clock.unix_timestamp),
+            ErrorCode::ClaimWindowOpen
+        );
+        require!(
+            !ctx.accounts.round_history.claimed,
+            ErrorCode::AlreadyClaimed
+        );
+        require!(
+            token_lottery.beneficiaries_settled(),
+            ErrorCode::BeneficiariesNotPaid
+        );
+        let amount = token_lottery.pot_amount;
+        require!(amount > 0, ErrorCode::NothingToClaim);
+
+        let destination = match token_lottery.unclaimed_policy {
+            // the pot stays put and restart_lottery carries it into the next round
+            UnclaimedPolicy::Rollover => None,
+            UnclaimedPolicy::Treasury => Some(token_lottery.treasury),
+            UnclaimedPolicy::Charity => Some(token_lottery.charity),
+        };
+
+        if let Some(destination) = destination {
+            let destination_token_account = ctx
+                .accounts
+                .destination_token_account
+                .as_ref()
+                .ok_or(ErrorCode::InvalidSweepDestination)?;
+            require!(
+                destination_token_account.owner == destination
+                    && destination_token_account.mint == ctx.accounts.token_mint.key(),
+                ErrorCode::InvalidSweepDestination
+            );
+
+            // token_lottery is signer authority for the vault
+            let seeds = &[b"token_lottery".as_ref(), &[token_lottery.bump]];
+            let signer = &[&seeds[..]];
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.raffle_vault_account.to_account_info(),
+                        to: destination_token_account.to_account_info(),
+                        mint: ctx.accounts.token_mint.to_account_info(),
+                        authority: ctx.accounts.token_lottery.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+                ctx.accounts.token_mint.decimals,
+            )?;
+            ctx.accounts.token_lottery.pot_amount = 0;
+        }
+
+        emit!(UnclaimedPrizeSwept {
+            round_id: ctx.accounts.token_lottery.round_id,
+            unclaimed_policy: ctx.accounts.token_lottery.unclaimed_policy,
+            destination: destination.unwrap_or_default(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn deposit_prize(ctx: Context<DepositPrize>, amount: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+        require!(!token_lottery.cancelled, ErrorCode::RoundCancelled);
+
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    mint: ctx.accounts.prize_mint.to_account_info(),
+                    from: ctx.accounts.authority_token_account.to_account_info(),
+                    to: ctx.accounts.prize_vault.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.prize_mint.decimals,
+        )?;
+
+        let round_prize = &mut ctx.accounts.round_prize;
+        if round_prize.amount == 0 {
+            ctx.accounts.round_history.outstanding_prizes = ctx
+                .accounts
+                .round_history
+                .outstanding_prizes
+                .checked_add(1)
+                .unwrap();
+        }
+        round_prize.round_id = token_lottery.round_id;
+        round_prize.mint = ctx.accounts.prize_mint.key();
+        round_prize.amount = round_prize.amount.checked_add(amount).unwrap();
+        round_prize.bump = ctx.bumps.round_prize;
+
+        emit!(PrizeDeposited {
+            round_id: round_prize.round_id,
+            mint: round_prize.mint,
+            amount,
+            total_amount: round_prize.amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn claim_prize(ctx: Context<ClaimPrize>) -> Result<()> {
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        require!(
+            ctx.accounts.token_lottery.winner_chosen,
+            ErrorCode::WinnerNotChosen
+        );
+
+        let clock = Clock::get()?;
+        let claim_deadline = ctx.accounts.token_lottery.claim_deadline;
+        require!(
+            claim_deadline == 0 || clock.unix_timestamp <= claim_deadline,
+            ErrorCode::ClaimDeadlinePassed
+        );
+        require!(!ctx.accounts.round_prize.claimed, ErrorCode::AlreadyClaimed);
+        require!(
+            ctx.accounts.round_prize.amount > 0,
+            ErrorCode::NothingToClaim
+        );
+
+        verify_winning_ticket(
+            &ctx.accounts.token_lottery,
+            &ctx.accounts.metadata,
+            ctx.accounts.collection_mint.key(),
+            &ctx.accounts.destination,
+        )?;
+
+        // token_lottery is signer authority for prize_vault
+        let seeds = &[
+            b"token_lottery".as_ref(),
+            &[ctx.accounts.token_lottery.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let amount = ctx.accounts.round_prize.amount;
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.prize_vault.to_account_info(),
+                    to: ctx.accounts.winner_prize_account.to_account_info(),
+                    mint: ctx.accounts.prize_mint.to_account_info(),
+                    authority: ctx.accounts.token_lottery.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.prize_mint.decimals,
+        )?;
+        ctx.accounts.round_prize.claimed = true;
+        ctx.accounts.round_history.outstanding_prizes = ctx
+            .accounts
+            .round_history
+            .outstanding_prizes
+            .checked_sub(1)
+            .unwrap();
+
+        emit!(PrizeClaimed {
+            round_id: ctx.accounts.round_prize.round_id,
+            mint: ctx.accounts.round_prize.mint,
+            amount,
+            winner: ctx.accounts.payer.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // prizes nobody can win anymore go back to the treasurer
+    pub fn withdraw_prize(ctx: Context<WithdrawPrize>, round_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &ctx.accounts.token_lottery;
+        if ctx.accounts.treasurer.key() != token_lottery.treasurer {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        require!(!token_lottery.claims_paused, ErrorCode::LotteryPaused);
+        require!(!ctx.accounts.round_prize.claimed, ErrorCode::AlreadyClaimed);
+        require!(
+            ctx.accounts.round_prize.amount > 0,
+            ErrorCode::NothingToClaim
+        );
+
+        let forfeited = if round_id == token_lottery.round_id {
+            // ended with nobody entered, or the winner missed the claim deadline
+            (!token_lottery.winner_chosen
+                && token_lottery.total_tickets == 0
+                && token_lottery.window_now(&clock) >= token_lottery.end_time)
+                || (token_lottery.winner_chosen
+                    && token_lottery.claim_deadline_passed(clock.unix_timestamp))
+        } else {
+            // claim_prize only pays out for the current round
+            true
+        };
+        require!(
+            ctx.accounts.round_history.cancelled || forfeited,
+            ErrorCode::PrizeStillClaimable
+        );
+
+        // token_lottery is signer authority for prize_vault
+        let seeds = &[
+            b"token_lottery".as_ref(),
+            &[ctx.accounts.token_lottery.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let amount = ctx.accounts.round_prize.amount;
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.prize_vault.to_account_info(),
+                    to: ctx.accounts.treasurer_token_account.to_account_info(),
+                    mint: ctx.accounts.prize_mint.to_account_info(),
+                    authority: ctx.accounts.token_lottery.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.prize_mint.decimals,
+        )?;
+        ctx.accounts.round_prize.amount = 0;
+        ctx.accounts.round_history.outstanding_prizes = ctx
+            .accounts
+            .round_history
+            .outstanding_prizes
+            .checked_sub(1)
+            .unwrap();
+
+        emit!(PrizeWithdrawn {
+            round_id,
+            mint: ctx.accounts.round_prize.mint,
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn withdraw_vested(ctx: Context<WithdrawVested>, round_id: u64) -> Result<()> {
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let clock = Clock::get()?;
+        let vesting_record = &ctx.accounts.vesting_record;
+        let amount = vesting_record
+            .unlocked_amount(clock.unix_timestamp)
+            .checked_sub(vesting_record.released_amount)
+            .unwrap();
+        require!(amount > 0, ErrorCode::NothingToClaim);
+
+        // token_lottery is signer authority for the vault
+        let seeds = &[
+            b"token_lottery".as_ref(),
+            &[ctx.accounts.token_lottery.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.raffle_vault_account.to_account_info(),
+                    to: ctx.accounts.beneficiary_token_account.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.token_lottery.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.token_mint.decimals,
+        )?;
+
+        let vesting_record = &mut ctx.accounts.vesting_record;
+        vesting_record.released_amount =
+            vesting_record.released_amount.checked_add(amount).unwrap();
+
+        emit!(VestedWithdrawn {
+            round_id,
+            beneficiary: vesting_record.beneficiary,
+            amount,
+            released_amount: vesting_record.released_amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // remaining accounts: one token account per beneficiary, in config order
+    pub fn distribute_beneficiaries<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeBeneficiaries<'info>>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let token_lottery = &ctx.accounts.token_lottery;
+        require!(token_lottery.winner_chosen, ErrorCode::WinnerNotChosen);
+        require!(
+            !token_lottery.beneficiaries_paid,
+            ErrorCode::BeneficiariesAlreadyPaid
+        );
+        require!(
+            ctx.remaining_accounts.len() == token_lottery.beneficiaries.len(),
+            ErrorCode::InvalidBeneficiaryAccounts
+        );
+
+        // token_lottery is signer authority for the vault
+        let seeds = &[b"token_lottery".as_ref(), &[token_lottery.bump]];
+        let signer = &[&seeds[..]];
+
+        let pot_amount = token_lottery.pot_amount;
+        let mut distributed: u64 = 0;
+        for (beneficiary, account) in token_lottery
+            .beneficiaries
+            .iter()
+            .zip(ctx.remaining_accounts.iter())
+        {
+            let beneficiary_token_account = InterfaceAccount::<TokenAccount>::try_from(account)?;
+            require!(
+                beneficiary_token_account.owner == beneficiary.wallet
+                    && beneficiary_token_account.mint == ctx.accounts.token_mint.key(),
+                ErrorCode::InvalidBeneficiaryAccounts
+            );
+
+            let amount = pot_amount
+                .checked_mul(beneficiary.bps as u64)
+                .unwrap()
+                .checked_div(10_000)
+                .unwrap();
+            if amount == 0 {
+                continue;
+            }
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.raffle_vault_account.to_account_info(),
+                        to: account.clone(),
+                        mint: ctx.accounts.token_mint.to_account_info(),
+                        authority: ctx.accounts.token_lottery.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+                ctx.accounts.token_mint.decimals,
+            )?;
+            distributed = distributed.checked_add(amount).unwrap();
+
+            emit!(BeneficiaryPaid {
+                round_id: token_lottery.round_id,
+                beneficiary: beneficiary.wallet,
+                bps: beneficiary.bps,
+                amount,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp
+            });
+        }
+
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        token_lottery.pot_amount = pot_amount.checked_sub(distributed).unwrap();
+        token_lottery.beneficiaries_paid = true;
+        Ok(())
+    }
+
+    // unset fields are left as they are
+    pub fn update_config(ctx: Context<UpdateConfig>, update: ConfigUpdate) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        // a drawn or cancelled round is changed through restart_lottery
+        require!(
+            !token_lottery.winner_chosen
+                && !token_lottery.cancelled
+                && !token_lottery.draw_requested,
+            ErrorCode::ConfigChangeNotAllowed
+        );
+
+        let now = token_lottery.window_now(&clock);
+        let round_open = now >= token_lottery.start_time;
+        if let Some(start_time) = update.start_time {
+            require!(!round_open, ErrorCode::ConfigChangeNotAllowed);
+            token_lottery.start_time = start_time;
+        }
+        if let Some(end_time) = update.end_time {
+            // an open round can be extended before it ends, never cut short or reopened
+            require!(
+                !round_open || (now < token_lottery.end_time && end_time >= token_lottery.end_time),
+                ErrorCode::ConfigChangeNotAllowed
+            );
+            token_lottery.end_time = end_time;
+        }
+        if let Some(max_tickets_per_wallet) = update.max_tickets_per_wallet {
+            if round_open {
+                validate_cap_change(
+                    token_lottery.max_tickets_per_wallet,
+                    max_tickets_per_wallet,
+                    0,
+                )?;
+            }
+            token_lottery.max_tickets_per_wallet = max_tickets_per_wallet;
+        }
+        if let Some(max_total_tickets) = update.max_total_tickets {
+            if round_open {
+                validate_cap_change(
+                    token_lottery.max_total_tickets,
+                    max_total_tickets,
+                    token_lottery.total_tickets,
+                )?;
+            }
+            token_lottery.max_total_tickets = max_total_tickets;
+            require!(
+                token_lottery.weighted_cap_ok(),
+                ErrorCode::WeightedTicketCap
+            );
+        }
+
+        validate_round_params(
+            token_lottery.start_time,
+            token_lottery.end_time,
+            token_lottery.ticket_price,
+            now,
+        )?;
+
+        emit!(ConfigUpdated {
+            round_id: token_lottery.round_id,
+            start_time: token_lottery.start_time,
+            end_time: token_lottery.end_time,
+            ticket_price: token_lottery.ticket_price,
+            max_tickets_per_wallet: token_lottery.max_tickets_per_wallet,
+            max_total_tickets: token_lottery.max_total_tickets,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn set_roles(
+        ctx: Context<SetRoles>,
+        operator: Pubkey,
+        treasurer: Pubkey,
+        pauser: Pubkey,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        token_lottery.operator = operator;
+        token_lottery.treasurer = treasurer;
+        token_lottery.pauser = pauser;
+
+        emit!(RolesUpdated {
+            operator,
+            treasurer,
+            pauser,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // start_time and end_time are given in the new mode's unit
+    pub fn set_window_mode(
+        ctx: Context<SetWindowMode>,
+        window_mode: WindowMode,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        // only before the current round opens and before anyone has entered it
+        require!(
+            token_lottery.total_tickets == 0
+                && !token_lottery.draw_requested
+                && token_lottery.window_now(&clock) < token_lottery.start_time,
+            ErrorCode::ConfigChangeNotAllowed
+        );
+
+        token_lottery.window_mode = window_mode;
+        validate_round_params(
+            start_time,
+            end_time,
+            token_lottery.ticket_price,
+            token_lottery.window_now(&clock),
+        )?;
+        token_lottery.start_time = start_time;
+        token_lottery.end_time = end_time;
+        // the presale cutoff, round schedule and launch bonus cutoff are in the old unit
+        token_lottery.presale_end_time = 0;
+        token_lottery.schedule = None;
+        token_lottery.weight_bonus.launch_end_time = 0;
+
+        emit!(WindowModeUpdated {
+            window_mode,
+            start_time,
+            end_time,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn set_weight_bonus(ctx: Context<SetWeightBonus>, weight_bonus: WeightBonus) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        // odds can't shift under tickets that were already sold
+        require!(
+            token_lottery.total_tickets == 0,
+            ErrorCode::ConfigChangeNotAllowed
+        );
+        require!(
+            weight_bonus.launch_weight >= 1 && weight_bonus.partner_weight >= 1,
+            ErrorCode::InvalidWeight
+        );
+        token_lottery.weight_bonus = weight_bonus;
+        require!(
+            token_lottery.weighted_cap_ok(),
+            ErrorCode::WeightedTicketCap
+        );
+
+        emit!(WeightBonusUpdated {
+            weight_bonus,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // permissionless, opens the next round from the schedule and mints its collection. On top of
+    // what restart_lottery checks, the previous round must be fully settled: refunds taken or
+    // closed, and an unclaimed pot swept unless the policy is to roll it over.
+    pub fn start_next_round(ctx: Context<StartNextRound>) -> Result<()> {
+        let token_lottery = &ctx.accounts.token_lottery;
+        require!(
+            !token_lottery.sales_paused && !token_lottery.claims_paused,
+            ErrorCode::LotteryPaused
+        );
+        let previous_round_history = &ctx.accounts.previous_round_history;
+        require!(
+            previous_round_history.refund_pool == 0,
+            ErrorCode::RefundsOutstanding
+        );
+        if token_lottery.winner_chosen && !previous_round_history.claimed {
+            require!(
+                token_lottery.unclaimed_policy == UnclaimedPolicy::Rollover
+                    || token_lottery.pot_amount == 0,
+                ErrorCode::UnclaimedNotSwept
+            );
+        }
+
+        let now = token_lottery.window_now(&Clock::get()?);
+        let schedule = token_lottery.schedule.ok_or(ErrorCode::NoRoundSchedule)?;
+
+        // a late call starts the round immediately instead of in the past
+        let start_time = token_lottery
+            .end_time
+            .checked_add(schedule.gap)
+            .unwrap()
+            .max(now);
+        let end_time = start_time.checked_add(schedule.round_duration).unwrap();
+
+        open_next_round(
+            &mut ctx.accounts.token_lottery,
+            &mut ctx.accounts.previous_round_history,
+            start_time,
+            end_time,
+            schedule.ticket_price,
+        )?;
+
+        create_round_collection(
+            ctx.accounts.round_collection(),
+            ctx.accounts.token_lottery.round_id,
+            ctx.bumps.collection_mint,
+        )?;
+
+        let round_history = &mut ctx.accounts.round_history;
+        round_history.round_id = ctx.accounts.token_lottery.round_id;
+        round_history.collection_mint = ctx.accounts.collection_mint.key();
+        round_history.bump = ctx.bumps.round_history;
+
+        emit!(InitializedLottery {
+            collection_mint: ctx.accounts.collection_mint.key()
+        });
+        Ok(())
+    }
+
+    // price, fee, payout, schedule and oracle changes only take effect after config_timelock
+    pub fn queue_config_change(
+        ctx: Context<QueueConfigChange>,
+        change: ConfigChange,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        // reject bad values up front rather than after the delay
+        match &change {
+            ConfigChange::TicketPrice(price) => {
+                require!(*price > 0, ErrorCode::InvalidTicketPrice)
+            }
+            ConfigChange::ReferralBps(bps) => {
+                require!(*bps <= 10_000, ErrorCode::InvalidBasisPoints)
+            }
+            ConfigChange::Beneficiaries(beneficiaries) => validate_beneficiaries(beneficiaries)?,
+            ConfigChange::OracleQueue(_) => {}
+            ConfigChange::Timelock(delay) => {
+                require!(
+                    (MIN_CONFIG_TIMELOCK..=MAX_CONFIG_TIMELOCK).contains(delay),
+                    ErrorCode::InvalidTimelock
+                )
+            }
+            ConfigChange::Vesting(vesting) => {
+                if let Some(schedule) = vesting {
+                    require!(
+                        schedule.duration > 0
+                            && schedule.instalments > 0
+                            && schedule.cliff >= 0
+                            && schedule.cliff <= schedule.duration,
+                        ErrorCode::InvalidVestingSchedule
+                    );
+                }
+            }
+            ConfigChange::ClaimPolicy(policy) => {
+                require!(
+                    policy.claim_window > 0 && policy.claim_window <= MAX_CLAIM_WINDOW,
+                    ErrorCode::InvalidClaimWindow
+                )
+            }
+            ConfigChange::Schedule(schedule) => {
+                if let Some(schedule) = schedule {
+                    require!(
+                        schedule.round_duration > 0 && schedule.gap >= 0,
+                        ErrorCode::InvalidRoundWindow
+                    );
+                    require!(schedule.ticket_price > 0, ErrorCode::InvalidTicketPrice);
+                }
+            }
+        }
+
+        let executable_at = clock
+            .unix_timestamp
+            .checked_add(token_lottery.config_timelock)
+            .ok_or(ErrorCode::InvalidTimelock)?;
+        let pending_config_change = &mut ctx.accounts.pending_config_change;
+        pending_config_change.proposer = ctx.accounts.authority.key();
+        pending_config_change.change = change.clone();
+        pending_config_change.executable_at = executable_at;
+        pending_config_change.bump = ctx.bumps.pending_config_change;
+
+        emit!(ConfigChangeQueued {
+            change,
+            executable_at,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // permissionless once the delay has passed
+    pub fn execute_config_change(ctx: Context<ExecuteConfigChange>) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= ctx.accounts.pending_config_change.executable_at,
+            ErrorCode::TimelockNotElapsed
+        );
+
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        let change = ctx.accounts.pending_config_change.change.clone();
+        match &change {
+            ConfigChange::TicketPrice(price) => {
+                // everyone in a round pays the same price
+                require!(
+                    token_lottery.total_tickets == 0,
+                    ErrorCode::ConfigChangeNotAllowed
+                );
+                token_lottery.ticket_price = *price;
+            }
+            ConfigChange::ReferralBps(bps) => {
+                token_lottery.referral_bps = *bps;
+                emit!(ReferralBpsUpdated {
+                    referral_bps: *bps,
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp
+                });
+            }
+            ConfigChange::Beneficiaries(beneficiaries) => {
+                // shares are taken from the pot being drawn, don't change them under it
+                require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+                token_lottery.beneficiaries = beneficiaries.clone();
+                emit!(BeneficiariesUpdated {
+                    beneficiaries: beneficiaries.clone(),
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp
+                });
+            }
+            ConfigChange::OracleQueue(oracle_queue) => {
+                token_lottery.oracle_queue = *oracle_queue;
+            }
+            ConfigChange::Timelock(delay) => {
+                token_lottery.config_timelock = *delay;
+            }
+            ConfigChange::Vesting(vesting) => {
+                // claim_winnings reads it, don't change it under a drawn pot
+                require!(!token_lottery.winner_chosen, ErrorCode::WinnerChosen);
+                token_lottery.vesting = *vesting;
+                emit!(VestingScheduleUpdated {
+                    vesting: *vesting,
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp
+                });
+            }
+            ConfigChange::ClaimPolicy(policy) => {
+                token_lottery.claim_window = policy.claim_window;
+                token_lottery.unclaimed_policy = policy.unclaimed_policy;
+                token_lottery.treasury = policy.treasury;
+                token_lottery.charity = policy.charity;
+                emit!(ClaimPolicyUpdated {
+                    claim_window: policy.claim_window,
+                    unclaimed_policy: policy.unclaimed_policy,
+                    treasury: policy.treasury,
+                    charity: policy.charity,
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp
+                });
+            }
+            ConfigChange::Schedule(schedule) => {
+                token_lottery.schedule = *schedule;
+                emit!(RoundScheduleUpdated {
+                    schedule: *schedule,
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp
+                });
+            }
+        }
+
+        emit!(ConfigChangeExecuted {
+            change,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn cancel_config_change(ctx: Context<CancelConfigChange>) -> Result<()> {
+        let clock = Clock::get()?;
+        if ctx.accounts.authority.key() != ctx.accounts.token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+
+        emit!(ConfigChangeCancelled {
+            change: ctx.accounts.pending_config_change.change.clone(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // the vrf callback ignores these so an in-flight draw still lands
+    pub fn set_paused(
+        ctx: Context<SetPaused>,
+        sales_paused: bool,
+        claims_paused: bool,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.signer.key() != token_lottery.authority
+            && ctx.accounts.signer.key() != token_lottery.pauser
+        {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        let now = clock.unix_timestamp;
+        if claims_paused && !token_lottery.claims_paused {
+            token_lottery.claims_paused_at = now;
+        } else if !claims_paused && token_lottery.claims_paused && token_lottery.claim_deadline != 0
+        {
+            // the winner doesn't lose claim time to the pause
+            token_lottery.claim_deadline = token_lottery
+                .claim_deadline
+                .checked_add(now.checked_sub(token_lottery.claims_paused_at).unwrap())
+                .unwrap();
+        }
+        token_lottery.sales_paused = sales_paused;
+        token_lottery.claims_paused = claims_paused;
+
+        emit!(PauseUpdated {
+            sales_paused,
+            claims_paused,
+            signer: ctx.accounts.signer.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        token_lottery.pending_authority = Some(new_authority);
+
+        emit!(AuthorityProposed {
+            authority: token_lottery.authority,
+            pending_authority: new_authority,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if token_lottery.pending_authority != Some(ctx.accounts.new_authority.key()) {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        let previous_authority = token_lottery.authority;
+        token_lottery.authority = ctx.accounts.new_authority.key();
+        token_lottery.pending_authority = None;
+
+        emit!(AuthorityTransferred {
+            previous_authority,
+            new_authority: token_lottery.authority,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    pub fn cancel_authority_transfer(ctx: Context<ProposeAuthority>) -> Result<()> {
+        let clock = Clock::get()?;
+        let token_lottery = &mut ctx.accounts.token_lottery;
+        if ctx.accounts.authority.key() != token_lottery.authority {
+            return Err(ErrorCode::NotAuthorized.into());
+        }
+        let pending_authority = token_lottery
+            .pending_authority
+            .take()
+            .ok_or(ErrorCode::NoPendingAuthority)?;
+
+        emit!(AuthorityTransferCancelled {
+            authority: token_lottery.authority,
+            pending_authority,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+
+    // permissionless, pays the prize to whoever currently holds the winning ticket
+    pub fn payout_winner(ctx: Context<PayoutWinner>) -> Result<()> {
+        let clock = Clock::get()?;
+        check_winnings_claimable(
+            &ctx.accounts.token_lottery,
+            &ctx.accounts.round_history,
+            clock.unix_timestamp,
+        )?;
+
+        let ticket_name = verify_winning_ticket(
+            &ctx.accounts.token_lottery,
+            &ctx.accounts.metadata,
+            ctx.accounts.collection_mint.key(),
+            &ctx.accounts.holder_ticket_account,
+        )?;
+
+        let payout = WinningsPayout {
+            token_lottery: ctx.accounts.token_lottery.to_account_info(),
+            reward_mint: ctx.accounts.reward_mint.to_account_info(),
+            reward_vault: ctx.accounts.reward_vault.to_account_info(),
+            recipient_token_account: ctx.accounts.holder_reward_account.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            decimals: ctx.accounts.reward_mint.decimals,
+        };
+        let amount = pay_winnings(
+            payout,
+            &mut ctx.accounts.token_lottery,
+            &mut ctx.accounts.round_history,
+            ctx.accounts.vesting_record.as_deref_mut(),
+            ctx.bumps.vesting_record,
+            ctx.accounts.holder.key(),
+            clock.unix_timestamp,
+        )?;
+
+        emit!(WinningsClaimed {
+            round_id: ctx.accounts.round_history.round_id,
+            ticket_name,
+            ticket_mint: ctx.accounts.ticket_mint.key(),
+            destination_account: ctx.accounts.holder_ticket_account.key(),
+            claimant: ctx.accounts.holder.key(),
+            caller: ctx.accounts.caller.key(),
+            recipient_token_account: ctx.accounts.holder_reward_account.key(),
+            amount
+        });
+        Ok(())
+    }
+
+    // permissionless, returns a settled weighted round's index rent to whoever paid for it
+    pub fn close_weight_index(ctx: Context<CloseWeightIndex>, round_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let round_history = &ctx.accounts.round_history;
+        require!(
+            round_history.winner_chosen || round_history.cancelled,
+            ErrorCode::RoundNotSettled
+        );
+
+        emit!(WeightIndexClosed {
+            round_id,
+            payer: ctx.accounts.payer.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+        Ok(())
+    }
+}
+
+// shared by restart_lottery and start_next_round
+pub fn open_next_round(
+    lottery: &mut TokenLottery,
+    round_history: &mut RoundHistory,
+    new_start_time: i64,
+    new_end_time: i64,
+    new_ticket_price: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+
+    // the previous round must be drawn, cancelled, or have ended with nobody entered
+    let settled = lottery.winner_chosen
+        || lottery.cancelled
+        || (lottery.total_tickets == 0 && lottery.window_now(&clock) >= lottery.end_time);
+    require!(settled, ErrorCode::RoundNotSettled);
+    // don't roll an unclaimed jackpot over while the winner can still claim it
+    if lottery.winner_chosen && !round_history.claimed {
+        require!(
+            lottery.claim_deadline_passed(clock.unix_timestamp),
+            ErrorCode::ClaimWindowOpen
+        );
+    }
+    // the beneficiaries' share of a drawn pot must not roll over with it
+    require!(
+        !lottery.winner_chosen || lottery.beneficiaries_settled(),
+        ErrorCode::BeneficiariesNotPaid
+    );
+    require!(
+        round_history.outstanding_prizes == 0,
+        ErrorCode::PrizesOutstanding
+    );
+    // thaw_tickets has to release every ticket frozen for the draw first
+    require!(
+        round_history.frozen_tickets == 0,
+        ErrorCode::TicketsStillFrozen
+    );
+    validate_round_params(
+        new_start_time,
+        new_end_time,
+        new_ticket_price,
+        lottery.window_now(&clock),
+    )?;
+
+    // whatever is left in the pot (unclaimed winnings, sponsor funds of a round nobody
+    // entered) carries into the next round instead of being orphaned in the vault
+    let rollover_amount = lottery.pot_amount;
+    round_history.rollover_amount = rollover_amount;
+
+    lottery.start_time = new_start_time;
+    lottery.end_time = new_end_time;
+    lottery.ticket_price = new_ticket_price;
+    lottery.total_tickets = 0;
+    lottery.winner_chosen = false;
+    lottery.draw_requested = false;
+    lottery.winner = 0;
+    lottery.pot_amount = rollover_amount;
+    lottery.sponsored_amount = 0;
+    lottery.ticket_sales = 0;
+    lottery.cancelled = false;
+    lottery.claim_deadline = 0;
+    lottery.beneficiaries_paid = false;
+    // bump round id to create fresh PDAs for next initialize_lottery
+    lottery.round_id = lottery.round_id.checked_add(1).unwrap();
+
+    if rollover_amount > 0 {
+        emit!(PotRolledOver {
+            from_round_id: round_history.round_id,
+            to_round_id: lottery.round_id,
+            amount: rollover_amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+    }
+
+    emit!(RoundStarted {
+        round_id: lottery.round_id,
+        start_time: new_start_time,
+        end_time: new_end_time,
+        ticket_price: new_ticket_price,
+        rollover_amount,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp
+    });
+    Ok(())
+}
+
+// holds a cancelled round's ticket sales and sponsor deposits back from the pot so they can
+// still be refunded after the lottery moves on. Every ticket gets its full price back: the
+// round's referral credits are never paid out, so their share of the vault joins the refunds.
+pub fn reserve_refunds(lottery: &mut TokenLottery, round_history: &mut RoundHistory, now: i64) {
+    let refund_amount = lottery.ticket_price;
+    let refund_pool = refund_amount
+        .checked_mul(lottery.total_tickets)
+        .unwrap()
+        .checked_add(lottery.sponsored_amount)
+        .unwrap();
+
+    lottery.cancelled = true;
+    lottery.pot_amount = lottery
+        .pot_amount
+        .checked_sub(lottery.ticket_sales)
+        .unwrap()
+        .checked_sub(lottery.sponsored_amount)
+        .unwrap();
+
+    round_history.cancelled = true;
+    round_history.total_tickets = lottery.total_tickets;
+    round_history.ticket_price = lottery.ticket_price;
+    round_history.refund_amount = refund_amount;
+    round_history.refund_pool = refund_pool;
+    round_history.refund_deadline = now.checked_add(REFUND_WINDOW).unwrap();
+}
+
+pub struct RoundCollection<'info> {
+    pub payer: AccountInfo<'info>,
+    pub collection_mint: AccountInfo<'info>,
+    pub collection_token_account: AccountInfo<'info>,
+    pub metadata: AccountInfo<'info>,
+    pub master_edition: AccountInfo<'info>,
+    pub token_metadata_program: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+}
+
+// mints the round's collection nft, used by initialize_lottery and start_next_round
+pub fn create_round_collection(
+    accounts: RoundCollection,
+    round_id: u64,
+    collection_bump: u8,
+) -> Result<()> {
+    // Store round_id bytes in a variable so they live long enough
+    let round_id_bytes = round_id.to_le_bytes();
+
+    // signer seeds for collection_mint PDA
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"collection_mint".as_ref(),
+        round_id_bytes.as_ref(),
+        &[collection_bump],
+    ]];
+    msg!("Creating Mint Account");
+    // Mint 1 token of the collection (collection supply/marker)
+    mint_to(
+        CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            MintTo {
+                mint: accounts.collection_mint.to_account_info(),
+                to: accounts.collection_token_account.to_account_info(),
+                authority: accounts.collection_mint.to_account_info(),
+            },
+            &signer_seeds,
+        ),
+        1,
+    )?;
+
+    msg!("Creating Metadata Account v3");
+    create_metadata_accounts_v3(
+        CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            CreateMetadataAccountsV3 {
+                metadata: accounts.metadata.to_account_info(),
+                mint: accounts.collection_mint.to_account_info(),
+                mint_authority: accounts.collection_mint.to_account_info(),
+                payer: accounts.payer.to_account_info(),
+                update_authority: accounts.collection_mint.to_account_info(),
+                system_program: accounts.system_program.to_account_info(),
+                rent: accounts.rent.to_account_info(),
+            },
+            &signer_seeds,
+        ),
+        DataV2 {
+            name: NAME.to_string(),
+            symbol: symbol.to_string(),
+            uri: url.to_string(),
+            seller_fee_basis_points: 0,
+            creators: Some(vec![Creator {
+                address: accounts.collection_mint.key(),
+                verified: false,
+                share: 100,
+            }]),
+            collection: None,
+            uses: None,
+        },
+        true,
+        true,
+        Some(CollectionDetails::V1 { size: 0 }),
+    );
+
+    msg!("Creating Master Edition Account");
+    create_master_edition_v3(
+        CpiContext::new_with_signer(
+            accounts.token_metadata_program.to_account_info(),
+            CreateMasterEditionV3 {
+                edition: accounts.master_edition.to_account_info(),
+                mint: accounts.collection_mint.to_account_info(),
+                update_authority: accounts.collection_mint.to_account_info(),
+                mint_authority: accounts.collection_mint.to_account_info(),
+                payer: accounts.payer.to_account_info(),
+                metadata: accounts.metadata.to_account_info(),
+                token_program: accounts.token_program.to_account_info(),
+                system_program: accounts.system_program.to_account_info(),
+                rent: accounts.rent.to_account_info(),
+            },
+            &signer_seeds,
+        ),
+        Some(0),
+    );
+
+    msg!("Verifying Collection...");
+    sign_metadata(CpiContext::new_with_signer(
+        accounts.token_program.to_account_info(),
+        SignMetadata {
+            creator: accounts.collection_mint.to_account_info(),
+            metadata: accounts.metadata.to_account_info(),
+        },
+        &signer_seeds,
+    ));
+
+    Ok(())
+}
+
+// a ticket gets the best bonus it qualifies for, 1 otherwise
+pub fn ticket_weight(
+    weight_bonus: &WeightBonus,
+    now: i64,
+    payer: Pubkey,
+    partner_token_account: Option<&InterfaceAccount<TokenAccount>>,
+) -> u64 {
+    let mut weight = 1u32;
+    if now < weight_bonus.launch_end_time {
+        weight = weight.max(weight_bonus.launch_weight);
+    }
+    if let Some(token_account) = partner_token_account {
+        if weight_bonus.partner_mint != Pubkey::default()
+            && token_account.mint == weight_bonus.partner_mint
+            && token_account.owner == payer
+            && token_account.amount >= weight_bonus.partner_min_amount
+        {
+            weight = weight.max(weight_bonus.partner_weight);
+        }
+    }
+    weight as u64
+}
+
+pub fn validate_round_params(start_time: i64, end_time: i64, price: u64, now: i64) -> Result<()> {
+    require!(
+        start_time < end_time && end_time > now,
+        ErrorCode::InvalidRoundWindow
+    );
+    require!(price > 0, ErrorCode::InvalidTicketPrice);
+    Ok(())
+}
+
+// cumulative holds the running weight totals, the winner is the first ticket whose total passes
+// the target
+pub fn pick_weighted_winner(cumulative: &[u64], randomness: u128) -> u64 {
+    let total_weight = cumulative[cumulative.len() - 1];
+    let target = (randomness % total_weight as u128) as u64;
+    cumulative.partition_point(|&total| total <= target) as u64
+}
+
+// 0 means no cap, so lifting a cap is always a raise
+pub fn validate_cap_change(current: u64, new: u64, sold: u64) -> Result<()> {
+    let raised = new == 0 || (current != 0 && new >= current);
+    require!(
+        raised && (new == 0 || new >= sold),
+        ErrorCode::ConfigChangeNotAllowed
+    );
+    Ok(())
+}
+
+pub fn validate_beneficiaries(beneficiaries: &[Beneficiary]) -> Result<()> {
+    require!(
+        beneficiaries.len() <= MAX_BENEFICIARIES as usize,
+        ErrorCode::TooManyBeneficiaries
+    );
+    let total_bps = beneficiaries
+        .iter()
+        .try_fold(0u16, |total, beneficiary| {
+            total.checked_add(beneficiary.bps)
+        })
+        .ok_or(ErrorCode::InvalidBasisPoints)?;
+    require!(total_bps <= 10_000, ErrorCode::InvalidBasisPoints);
+    Ok(())
+}
+
+// sorted-pair merkle proof, hashed with the same sha256 `hash` used for leaves
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let mut computed = leaf;
+    for node in proof {
+        computed = if computed <= *node {
+            hash(&[computed, *node].concat()).to_bytes()
+        } else {
+            hash(&[*node, computed].concat()).to_bytes()
+        };
+    }
+    computed == root
+}
+
+// the ticket must be the round's winning ticket, from the round's verified collection, held by
+// the claimer. Returns the ticket name.
+pub fn verify_winning_ticket(
+    token_lottery: &TokenLottery,
+    metadata: &MetadataAccount,
+    collection_mint: Pubkey,
+    ticket_account: &TokenAccount,
+) -> Result<String> {
+    // Check if token is a part of the collection
+    require!(
+        metadata.collection.as_ref().unwrap().verified,
+        ErrorCode::NotVerifiedTicket
+    );
+    require!(
+        metadata.collection.as_ref().unwrap().key == collection_mint,
+        ErrorCode::IncorrectTicket
+    );
+
+    let ticket_name = NAME.to_owned() + &token_lottery.winner.to_string();
+    let metadata_name = metadata.name.replace("\u{0}", "");
+
+    msg!("Ticket name: {}", ticket_name);
+    msg!("Metdata name: {}", metadata_name);
+
+    // Check if the winner has the winning ticket
+    require!(metadata_name == ticket_name, ErrorCode::IncorrectTicket);
+    require!(ticket_account.amount > 0, ErrorCode::IncorrectTicket);
+
+    Ok(ticket_name)
+}
+
+// shared by claim_winnings and payout_winner
+pub fn check_winnings_claimable(
+    token_lottery: &TokenLottery,
+    round_history: &RoundHistory,
+    now: i64,
+) -> Result<()> {
+    require!(!token_lottery.claims_paused, ErrorCode::LotteryPaused);
+    require!(token_lottery.winner_chosen, ErrorCode::WinnerNotChosen);
+    require!(
+        token_lottery.claim_deadline == 0 || now <= token_lottery.claim_deadline,
+        ErrorCode::ClaimDeadlinePassed
+    );
+    require!(!round_history.claimed, ErrorCode::AlreadyClaimed);
+    require!(
+        token_lottery.beneficiaries_settled(),
+        ErrorCode::BeneficiariesNotPaid
+    );
+    Ok(())
+}
+
+pub struct WinningsPayout<'info> {
+    pub token_lottery: AccountInfo<'info>,
+    pub reward_mint: AccountInfo<'info>,
+    pub reward_vault: AccountInfo<'info>,
+    pub recipient_token_account: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub decimals: u8,
+}
+
+// pays the pot to the winning ticket's holder, or vests it for them when the lottery has a
+// vesting schedule, and records the claim. Returns the amount.
+pub fn pay_winnings<'info>(
+    accounts: WinningsPayout<'info>,
+    token_lottery: &mut TokenLottery,
+    round_history: &mut RoundHistory,
+    vesting_record: Option<&mut Account<'info, VestingRecord>>,
+    vesting_bump: Option<u8>,
+    winner: Pubkey,
+    now: i64,
+) -> Result<u64> {
+    let clock = Clock::get()?;
+    let amount = token_lottery.pot_amount;
+    if let Some(vesting) = token_lottery.vesting {
+        // the pot stays in the vault and is released over time by withdraw_vested
+        let vesting_record = vesting_record.ok_or(ErrorCode::VestingRecordMissing)?;
+        vesting_record.round_id = token_lottery.round_id;
+        vesting_record.beneficiary = winner;
+        vesting_record.total_amount = amount;
+        vesting_record.released_amount = 0;
+        vesting_record.start_time = now;
+        vesting_record.schedule = vesting;
+        vesting_record.bump = vesting_bump.unwrap();
+
+        emit!(WinningsVested {
+            round_id: vesting_record.round_id,
+            beneficiary: winner,
+            total_amount: amount,
+            start_time: now,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp
+        });
+    } else {
+        // token_lottery is signer authority for reward_vault
+        let seeds = &[b"token_lottery".as_ref(), &[token_lottery.bump]];
+        let signer = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                accounts.token_program,
+                TransferChecked {
+                    from: accounts.reward_vault,
+                    to: accounts.recipient_token_account,
+                    mint: accounts.reward_mint,
+                    authority: accounts.token_lottery,
+                },
+                signer,
+            ),
+            amount,
+            accounts.decimals,
+        )?;
+    }
+    token_lottery.pot_amount = 0;
+
+    round_history.claimed = true;
+    round_history.claimant = winner;
+    round_history.claimed_amount = amount;
+    round_history.claimed_at = now;
+    Ok(amount)
+}
+
+// collection gates are checked the same way claim_winnings checks a ticket's collection
+pub fn check_entry_gate(
+    entry_gate: EntryGate,
+    payer: Pubkey,
+    gate_token_account: Option<&InterfaceAccount<TokenAccount>>,
+    gate_metadata: Option<&Account<MetadataAccount>>,
+) -> Result<()> {
+    match entry_gate {
+        EntryGate::None => Ok(()),
+        EntryGate::Collection { mint } => {
+            let token_account = gate_token_account.ok_or(ErrorCode::EntryGateNotMet)?;
+            let metadata = gate_metadata.ok_or(ErrorCode::EntryGateNotMet)?;
+            require!(
+                token_account.owner == payer && token_account.amount > 0,
+                ErrorCode::EntryGateNotMet
+            );
+            require!(
+                metadata.mint == token_account.mint,
+                ErrorCode::EntryGateNotMet
+            );
+            require!(
+                metadata
+                    .collection
+                    .as_ref()
+                    .is_some_and(|collection| collection.verified && collection.key == mint),
+                ErrorCode::EntryGateNotMet
+            );
+            Ok(())
+        }
+        EntryGate::Token { mint, min_amount } => {
+            let token_account = gate_token_account.ok_or(ErrorCode::EntryGateNotMet)?;
+            require!(
+                token_account.owner == payer
+                    && token_account.mint == mint
+                    && token_account.amount >= min_amount,
+                ErrorCode::EntryGateNotMet
+            );
+            Ok(())
+        }
+    }
+}
+
+// ---------------------------- Accounts ---------------------------- //
+
+#[derive(Accounts)]
+pub struct InitializeLottery<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // collection_mint is now round-scoped (seed includes token_lottery.round_id)
+    #[account(
+        init,
+        payer = payer,
+        mint::decimals = 0,
+        mint::authority = collection_mint,
+        mint::freeze_authority = collection_mint,
+        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub collection_mint: InterfaceAccount<'info, Mint>,
+
+    // collection_token_account is the ATA for collection_mint owned by collection_mint PDA
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = collection_mint,
+        associated_token::authority = collection_mint,
+    )]
+    pub collection_token_account: InterfaceAccount<'info, TokenAccount>,
 
     // metadata PDA for the collection mint (metaplex)
     #[account(
-        mut,
-        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key()
+    )]
+    /// CHECK: checked by metadata program
+    pub metadata: UncheckedAccount<'info>,
+
+    // token_lottery config (persistent)
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    // per-round record that outlives restart_lottery
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + RoundHistory::INIT_SPACE,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    #[account(
+        mut, 
+        seeds=[b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref(), b"edition"], 
+        bump, 
+        seeds::program=token_metadata_program
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub master_edition: UncheckedAccount<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+impl<'info> InitializeLottery<'info> {
+    pub fn round_collection(&self) -> RoundCollection<'info> {
+        RoundCollection {
+            payer: self.payer.to_account_info(),
+            collection_mint: self.collection_mint.to_account_info(),
+            collection_token_account: self.collection_token_account.to_account_info(),
+            metadata: self.metadata.to_account_info(),
+            master_edition: self.master_edition.to_account_info(),
+            token_metadata_program: self.token_metadata_program.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+            system_program: self.system_program.to_account_info(),
+            rent: self.rent.to_account_info(),
+        }
+    }
+}
+
+#[vrf]
+#[derive(Accounts)]
+pub struct CommitWinner<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds=[b"token_lottery".as_ref()],
+        bump=token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    // weighted rounds only
+    #[account(
+        seeds = [b"weight_index".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = weight_index.load()?.bump
+    )]
+    pub weight_index: Option<AccountLoader<'info, WeightIndex>>,
+
+    /// CHECK: The oracle queue
+    #[account(mut, address = token_lottery.oracle_queue)]
+    pub oracle_queue: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init, 
+        payer=signer,
+        space=8+ TokenLottery::INIT_SPACE,
+        seeds=[b"token_lottery".as_ref()],
+        bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    // mint tickets are paid in and prizes are paid out in
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinnings<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    // only used when the lottery has a vesting schedule
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + VestingRecord::INIT_SPACE,
+        seeds = [b"vesting".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub vesting_record: Option<Box<Account<'info, VestingRecord>>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = reward_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub winner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // collection_mint must match the round's collection mint (should include round_id)
+    #[account(
+        mut,
+        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub collection_mint: InterfaceAccount<'info, Mint>,
+
+    // ticket mint derived from round_id + ticket index / winner
+    #[account(
+        seeds = [token_lottery.round_id.to_le_bytes().as_ref(), token_lottery.winner.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub ticket_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub metadata: Account<'info, MetadataAccount>,
+
+    #[account(
+        associated_token::mint = ticket_mint,
+        associated_token::authority = payer,
+        associated_token::token_program = token_program,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub collection_metadata: Account<'info, MetadataAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub token_metadata_program: Program<'info, Metadata>,
+}
+
+#[derive(Accounts)]
+pub struct RestartLottery<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump,
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    // history of the round being closed, records the rollover
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CallbackChooseWinnerCtx<'info> {
+    /// CHECK: VRF program identity
+    #[account(address = ephemeral_vrf_sdk::consts::VRF_PROGRAM_IDENTITY)]
+    pub vrf_program_identity: Signer<'info>,
+
+    #[account(mut)]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    // weighted rounds only
+    #[account(
+        seeds = [b"weight_index".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = weight_index.load()?.bump
+    )]
+    pub weight_index: Option<AccountLoader<'info, WeightIndex>>,
+}
+
+#[derive(Accounts)]
+pub struct BuyTicket<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds=[b"token_lottery".as_ref()],
+        bump=token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        constraint = payer_token_account.mint == token_mint.key(),
+        constraint = payer_token_account.owner == payer.key(),
+    )]
+    pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // Vault for reward tokens (init_if_needed so we don't re-init a vault that already exists)
+    #[account(
+       init_if_needed,
+       payer = payer,
+       associated_token::mint = token_mint,
+       associated_token::authority = token_lottery,
+       associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    // registered referrer credited with a share of this purchase
+    #[account(mut)]
+    pub referral_accrual: Option<Box<Account<'info, ReferralAccrual>>>,
+
+    // the referrer's credits for this round, required with referral_accrual
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + RoundReferral::INIT_SPACE,
+        seeds = [
+            b"round_referral".as_ref(),
+            token_lottery.round_id.to_le_bytes().as_ref(),
+            referral_accrual.as_ref().ok_or(ErrorCode::InvalidReferralAccounts)?.referrer.as_ref()
+        ],
+        bump
+    )]
+    pub round_referral: Option<Box<Account<'info, RoundReferral>>>,
+
+    // only needed when the lottery has an entry gate
+    pub gate_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub gate_metadata: Option<Box<Account<'info, MetadataAccount>>>,
+
+    // partner token holding that earns the bonus weight, if any
+    pub partner_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    // weighted rounds only, created by the round's first purchase and takes one running total
+    // per ticket
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<WeightIndex>(),
+        seeds = [b"weight_index".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub weight_index: Option<AccountLoader<'info, WeightIndex>>,
+
+    // tickets bought by payer this round, enforces max_tickets_per_wallet
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + PlayerEntry::INIT_SPACE,
+        seeds = [b"player_entry".as_ref(), token_lottery.round_id.to_le_bytes().as_ref(), payer.key().as_ref()],
+        bump
+    )]
+    pub player_entry: Box<Account<'info, PlayerEntry>>,
+
+    // ticket_mint is now round-scoped + ticket index to avoid collisions
+    #[account(
+        init,
+        payer=payer,
+        seeds=[token_lottery.round_id.to_le_bytes().as_ref(), token_lottery.total_tickets.to_le_bytes().as_ref()],
+        bump,
+        mint::decimals=0,
+        mint::authority=collection_mint,
+        mint::freeze_authority=collection_mint,
+        mint::token_program=token_program
+    )]
+    pub ticket_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut, 
+        seeds=[b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
+        bump,
+        seeds::program=token_metadata_program.key()
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub ticket_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer=payer,
+        associated_token::mint=ticket_mint,
+        associated_token::authority=payer,
+        associated_token::token_program=token_program
+    )]
+    pub destination: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut, 
+        seeds=[b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
+        bump,
+        seeds::program=token_metadata_program.key()
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub collection_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        mut, 
+        seeds=[b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref(), b"edition"], 
+        bump, 
+        seeds::program=token_metadata_program
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub ticket_master_edition: UncheckedAccount<'info>,
+
+    #[account(
+        mut, 
+        seeds=[b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref(), b"edition"], 
+        bump, 
+        seeds::program=token_metadata_program
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub collection_master_edition: UncheckedAccount<'info>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+
+    // collection_mint is round-scoped
+    #[account(
+        mut,
+        seeds=[b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), 
+        ticket_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    /// CHECK: This account will be initialized by the metaplex program
+    pub metadata: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), 
+            ticket_mint.key().as_ref(), b"edition"],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    /// CHECK: This account will be initialized by the metaplex program
+    pub master_edition: UncheckedAccount<'info>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeTickets<'info> {
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    // collection_mint is the delegate of every ticket sold this round
+    #[account(
+        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub collection_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct ThawTickets<'info> {
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    #[account(
+        seeds = [b"collection_mint".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub collection_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64, ticket_index: u64)]
+pub struct BurnTicket<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    #[account(
+        seeds = [b"collection_mint".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [round_id.to_le_bytes().as_ref(), ticket_index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub ticket_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = ticket_mint,
+        associated_token::authority = payer,
+        associated_token::token_program = token_program,
+    )]
+    pub ticket_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key()
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub ticket_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref(), b"edition"],
+        bump,
+        seeds::program = token_metadata_program
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub ticket_master_edition: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key()
+    )]
+    ///CHECK: These are checked by the token metadata program
+    pub collection_metadata: UncheckedAccount<'info>,
+
+    // only for a cancelled round, proves the ticket was refunded
+    #[account(
+        seeds = [b"ticket_refund".as_ref(), round_id.to_le_bytes().as_ref(), ticket_index.to_le_bytes().as_ref()],
+        bump = ticket_refund.bump
+    )]
+    pub ticket_refund: Option<Box<Account<'info, TicketRefund>>>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetTicketCaps<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPresale<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEntryGate<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + ReferralAccrual::INIT_SPACE,
+        seeds = [b"referral".as_ref(), referrer.key().as_ref()],
+        bump
+    )]
+    pub referral_accrual: Account<'info, ReferralAccrual>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct ClaimReferralRewards<'info> {
+    pub referrer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referral".as_ref(), referrer.key().as_ref()],
+        bump = referral_accrual.bump
+    )]
+    pub referral_accrual: Account<'info, ReferralAccrual>,
+
+    #[account(
+        mut,
+        seeds = [b"round_referral".as_ref(), round_id.to_le_bytes().as_ref(), referrer.key().as_ref()],
+        bump = round_referral.bump
+    )]
+    pub round_referral: Account<'info, RoundReferral>,
+
+    #[account(
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = referrer_token_account.mint == token_mint.key(),
+        constraint = referrer_token_account.owner == referrer.key(),
+    )]
+    pub referrer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FundPot<'info> {
+    #[account(mut)]
+    pub sponsor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = sponsor_token_account.mint == token_mint.key(),
+        constraint = sponsor_token_account.owner == sponsor.key(),
+    )]
+    pub sponsor_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = sponsor,
+        associated_token::mint = token_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = sponsor,
+        space = 8 + SponsorContribution::INIT_SPACE,
+        seeds = [b"sponsor".as_ref(), token_lottery.round_id.to_le_bytes().as_ref(), sponsor.key().as_ref()],
+        bump
+    )]
+    pub sponsor_contribution: Box<Account<'info, SponsorContribution>>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRound<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EnterRefundMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct RefundSponsor<'info> {
+    pub sponsor: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(
+        mut,
+        seeds = [b"sponsor".as_ref(), round_id.to_le_bytes().as_ref(), sponsor.key().as_ref()],
+        bump = sponsor_contribution.bump
+    )]
+    pub sponsor_contribution: Box<Account<'info, SponsorContribution>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = sponsor_token_account.mint == token_mint.key(),
+        constraint = sponsor_token_account.owner == sponsor.key(),
+    )]
+    pub sponsor_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SweepUnclaimed<'info> {
+    pub treasurer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // treasury or charity token account, not needed when the pot rolls over
+    #[account(mut)]
+    pub destination_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct DepositPrize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    pub prize_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = authority_token_account.mint == prize_mint.key(),
+        constraint = authority_token_account.owner == authority.key(),
+    )]
+    pub authority_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + RoundPrize::INIT_SPACE,
+        seeds = [b"prize".as_ref(), token_lottery.round_id.to_le_bytes().as_ref(), prize_mint.key().as_ref()],
+        bump
+    )]
+    pub round_prize: Box<Account<'info, RoundPrize>>,
+
+    // escrow owned by token_lottery, kept apart from raffle_vault_account
+    #[account(
+        init_if_needed,
+        payer = authority,
+        token::mint = prize_mint,
+        token::authority = token_lottery,
+        token::token_program = token_program,
+        seeds = [b"prize_vault".as_ref(), token_lottery.round_id.to_le_bytes().as_ref(), prize_mint.key().as_ref()],
+        bump
+    )]
+    pub prize_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimPrize<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(
+        mut,
+        seeds = [b"prize".as_ref(), token_lottery.round_id.to_le_bytes().as_ref(), prize_mint.key().as_ref()],
+        bump = round_prize.bump
+    )]
+    pub round_prize: Box<Account<'info, RoundPrize>>,
+
+    pub prize_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"prize_vault".as_ref(), token_lottery.round_id.to_le_bytes().as_ref(), prize_mint.key().as_ref()],
+        bump
+    )]
+    pub prize_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = prize_mint,
+        associated_token::authority = payer,
+        associated_token::token_program = token_program,
+    )]
+    pub winner_prize_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [token_lottery.round_id.to_le_bytes().as_ref(), token_lottery.winner.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub ticket_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key(),
+    )]
+    pub metadata: Box<Account<'info, MetadataAccount>>,
+
+    #[account(
+        associated_token::mint = ticket_mint,
+        associated_token::authority = payer,
+        associated_token::token_program = token_program,
+    )]
+    pub destination: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct WithdrawPrize<'info> {
+    pub treasurer: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(
+        mut,
+        seeds = [b"prize".as_ref(), round_id.to_le_bytes().as_ref(), prize_mint.key().as_ref()],
+        bump = round_prize.bump
+    )]
+    pub round_prize: Box<Account<'info, RoundPrize>>,
+
+    pub prize_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"prize_vault".as_ref(), round_id.to_le_bytes().as_ref(), prize_mint.key().as_ref()],
+        bump
+    )]
+    pub prize_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = treasurer_token_account.mint == prize_mint.key(),
+        constraint = treasurer_token_account.owner == treasurer.key(),
+    )]
+    pub treasurer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct WithdrawVested<'info> {
+    pub beneficiary: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = beneficiary,
+        seeds = [b"vesting".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = vesting_record.bump
+    )]
+    pub vesting_record: Box<Account<'info, VestingRecord>>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = beneficiary_token_account.mint == token_mint.key(),
+        constraint = beneficiary_token_account.owner == beneficiary.key(),
+    )]
+    pub beneficiary_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeBeneficiaries<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PayoutWinner<'info> {
+    // pays rent for the holder's reward account if it doesn't exist yet
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// CHECK: must own holder_ticket_account, which holds the winning ticket
+    pub holder: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    // only used when the lottery has a vesting schedule
+    #[account(
+        init,
+        payer = caller,
+        space = 8 + VestingRecord::INIT_SPACE,
+        seeds = [b"vesting".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub vesting_record: Option<Box<Account<'info, VestingRecord>>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub reward_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = reward_mint,
+        associated_token::authority = token_lottery,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = caller,
+        associated_token::mint = reward_mint,
+        associated_token::authority = holder,
+        associated_token::token_program = token_program,
+    )]
+    pub holder_reward_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
         bump,
-        seeds::program = token_metadata_program.key()
     )]
-    /// CHECK: checked by metadata program
-    pub metadata: UncheckedAccount<'info>,
+    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
 
-    // token_lottery config (persistent)
     #[account(
-        seeds = [b"token_lottery".as_ref()],
-        bump = token_lottery.bump
+        seeds = [token_lottery.round_id.to_le_bytes().as_ref(), token_lottery.winner.to_le_bytes().as_ref()],
+        bump,
     )]
-    pub token_lottery: Account<'info, TokenLottery>,
+    pub ticket_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
-        mut, 
-        seeds=[b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref(), b"edition"], 
-        bump, 
-        seeds::program=token_metadata_program
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key(),
     )]
-    ///CHECK: These are checked by the token metadata program
-    pub master_edition: UncheckedAccount<'info>,
-    pub token_metadata_program: Program<'info, Metadata>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub metadata: Box<Account<'info, MetadataAccount>>,
+
+    #[account(
+        associated_token::mint = ticket_mint,
+        associated_token::authority = holder,
+        associated_token::token_program = token_program,
+    )]
+    pub holder_ticket_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
     pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    pub token_metadata_program: Program<'info, Metadata>,
 }
 
-#[vrf]
 #[derive(Accounts)]
-pub struct CommitWinner<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
+#[instruction(round_id: u64)]
+pub struct CloseWeightIndex<'info> {
+    #[account(
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
 
     #[account(
         mut,
-        seeds=[b"token_lottery".as_ref()],
-        bump=token_lottery.bump
+        close = payer,
+        seeds = [b"weight_index".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = weight_index.load()?.bump
+    )]
+    pub weight_index: AccountLoader<'info, WeightIndex>,
+
+    /// CHECK: the buyer who created the index, only receives its rent
+    #[account(mut, address = weight_index.load()?.payer)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
     )]
     pub token_lottery: Account<'info, TokenLottery>,
 
-    /// CHECK: The oracle queue
-    #[account(mut, address = ephemeral_vrf_sdk::consts::DEFAULT_QUEUE)]
-    pub oracle_queue: AccountInfo<'info>,
-    pub system_program: Program<'info, System>,
+    pub authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct InitializeConfig<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
+pub struct SetRoles<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
 
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinParticipation<'info> {
     #[account(
-        init, 
-        payer=signer,
-        space=8+ TokenLottery::INIT_SPACE,
-        seeds=[b"token_lottery".as_ref()],
-        bump
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
     )]
     pub token_lottery: Account<'info, TokenLottery>,
 
-    pub system_program: Program<'info, System>,
+    pub authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimWinnings<'info> {
+#[instruction(round_id: u64, ticket_index: u64)]
+pub struct RefundTicket<'info> {
     #[account(mut)]
-    pub payer: Signer<'info>,
+    pub holder: Signer<'info>,
 
     #[account(
-        mut,
         seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(
+        seeds = [round_id.to_le_bytes().as_ref(), ticket_index.to_le_bytes().as_ref()],
         bump
     )]
-    pub token_lottery: Account<'info, TokenLottery>,
+    pub ticket_mint: Box<InterfaceAccount<'info, Mint>>,
 
-    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = ticket_mint,
+        associated_token::authority = holder,
+        associated_token::token_program = token_program,
+    )]
+    pub ticket_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // existence marks the ticket as refunded
+    #[account(
+        init,
+        payer = holder,
+        space = 8 + TicketRefund::INIT_SPACE,
+        seeds = [b"ticket_refund".as_ref(), round_id.to_le_bytes().as_ref(), ticket_index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub ticket_refund: Box<Account<'info, TicketRefund>>,
+
+    #[account(address = token_lottery.token_mint)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         mut,
-        associated_token::mint = reward_mint,
+        associated_token::mint = token_mint,
         associated_token::authority = token_lottery,
         associated_token::token_program = token_program,
     )]
-    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = holder_token_account.mint == token_mint.key(),
+        constraint = holder_token_account.owner == holder.key(),
+    )]
+    pub holder_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct CloseRefunds<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        seeds = [b"round_history".as_ref(), round_id.to_le_bytes().as_ref()],
+        bump = round_history.bump
+    )]
+    pub round_history: Account<'info, RoundHistory>,
+}
+
+#[derive(Accounts)]
+pub struct SetWeightBonus<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
 
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetWindowMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    pub authority: Signer<'info>,
+}
+
+// same accounts as InitializeLottery, but for the round after the current one
+#[derive(Accounts)]
+pub struct StartNextRound<'info> {
     #[account(mut)]
-    pub winner_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub payer: Signer<'info>,
 
-    // collection_mint must match the round's collection mint (should include round_id)
     #[account(
         mut,
-        seeds = [b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
-        bump,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
     )]
-    pub collection_mint: InterfaceAccount<'info, Mint>,
+    pub token_lottery: Box<Account<'info, TokenLottery>>,
 
-    // ticket mint derived from round_id + ticket index / winner
     #[account(
-        seeds = [token_lottery.round_id.to_le_bytes().as_ref(), token_lottery.winner.to_le_bytes().as_ref()],
+        mut,
+        seeds = [b"round_history".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
+        bump = previous_round_history.bump
+    )]
+    pub previous_round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + RoundHistory::INIT_SPACE,
+        seeds = [b"round_history".as_ref(), (token_lottery.round_id + 1).to_le_bytes().as_ref()],
+        bump
+    )]
+    pub round_history: Box<Account<'info, RoundHistory>>,
+
+    #[account(
+        init,
+        payer = payer,
+        mint::decimals = 0,
+        mint::authority = collection_mint,
+        mint::freeze_authority = collection_mint,
+        seeds = [b"collection_mint".as_ref(), (token_lottery.round_id + 1).to_le_bytes().as_ref()],
+        bump
+    )]
+    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = collection_mint,
+        associated_token::authority = collection_mint,
+    )]
+    pub collection_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
         bump,
+        seeds::program = token_metadata_program.key()
     )]
-    pub ticket_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: checked by metadata program
+    pub metadata: UncheckedAccount<'info>,
 
     #[account(
-        seeds = [b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref(), b"edition"],
         bump,
-        seeds::program = token_metadata_program.key(),
+        seeds::program = token_metadata_program
     )]
-    pub metadata: Account<'info, MetadataAccount>,
+    /// CHECK: checked by metadata program
+    pub master_edition: UncheckedAccount<'info>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+impl<'info> StartNextRound<'info> {
+    pub fn round_collection(&self) -> RoundCollection<'info> {
+        RoundCollection {
+            payer: self.payer.to_account_info(),
+            collection_mint: self.collection_mint.to_account_info(),
+            collection_token_account: self.collection_token_account.to_account_info(),
+            metadata: self.metadata.to_account_info(),
+            master_edition: self.master_edition.to_account_info(),
+            token_metadata_program: self.token_metadata_program.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+            system_program: self.system_program.to_account_info(),
+            rent: self.rent.to_account_info(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct QueueConfigChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
 
     #[account(
-        associated_token::mint = ticket_mint,
-        associated_token::authority = payer,
-        associated_token::token_program = token_program,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    // one change in flight at a time
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingConfigChange::INIT_SPACE,
+        seeds = [b"pending_config".as_ref()],
+        bump
+    )]
+    pub pending_config_change: Account<'info, PendingConfigChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteConfigChange<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
+    pub token_lottery: Account<'info, TokenLottery>,
+
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [b"pending_config".as_ref()],
+        bump = pending_config_change.bump
+    )]
+    pub pending_config_change: Account<'info, PendingConfigChange>,
+
+    /// CHECK: receives the rent of the pending change
+    #[account(mut, address = pending_config_change.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelConfigChange<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
     )]
-    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_lottery: Account<'info, TokenLottery>,
 
     #[account(
         mut,
-        seeds = [b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
-        bump,
-        seeds::program = token_metadata_program.key(),
+        close = proposer,
+        seeds = [b"pending_config".as_ref()],
+        bump = pending_config_change.bump
     )]
-    pub collection_metadata: Account<'info, MetadataAccount>,
+    pub pending_config_change: Account<'info, PendingConfigChange>,
 
-    pub token_program: Interface<'info, TokenInterface>,
-    pub system_program: Program<'info, System>,
-    pub token_metadata_program: Program<'info, Metadata>,
+    /// CHECK: receives the rent of the pending change
+    #[account(mut, address = pending_config_change.proposer)]
+    pub proposer: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct RestartLottery<'info> {
+pub struct SetPaused<'info> {
     #[account(
         mut,
         seeds = [b"token_lottery".as_ref()],
-        bump = token_lottery.bump,
+        bump = token_lottery.bump
     )]
     pub token_lottery: Account<'info, TokenLottery>,
 
-    pub authority: Signer<'info>,
+    pub signer: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct CallbackChooseWinnerCtx<'info> {
-    /// CHECK: VRF program identity
-    #[account(address = ephemeral_vrf_sdk::consts::VRF_PROGRAM_IDENTITY)]
-    pub vrf_program_identity: Signer<'info>,
-
-    #[account(mut)]
+pub struct ProposeAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
+    )]
     pub token_lottery: Account<'info, TokenLottery>,
+
+    pub authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct BuyTicket<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
-
+pub struct AcceptAuthority<'info> {
     #[account(
         mut,
-        seeds=[b"token_lottery".as_ref()],
-        bump=token_lottery.bump
+        seeds = [b"token_lottery".as_ref()],
+        bump = token_lottery.bump
     )]
-    pub token_lottery: Box<Account<'info, TokenLottery>>,
+    pub token_lottery: Account<'info, TokenLottery>,
 
-    #[account(
-        mut,
-        constraint = payer_token_account.mint == token_mint.key(),
-        constraint = payer_token_account.owner == payer.key(),
-    )]
-    pub payer_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub new_authority: Signer<'info>,
+}
 
-    // Vault for reward tokens (init_if_needed so we don't re-init a vault that already exists)
-    #[account(
-       init_if_needed,
-       payer = payer,
-       associated_token::mint = token_mint,
-       associated_token::authority = token_lottery,
-       associated_token::token_program = token_program,
-    )]
-    pub raffle_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+// ---------------------------- Errors & Events & State ---------------------------- //
 
-    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Lottery is not open yet.")]
+    LotteryNotOpen,
+    #[msg("You're Not Authorized!.")]
+    NotAuthorized,
+    #[msg("Randomnes Already Revealed")]
+    RandomnessAlreadyRevealed,
+    #[msg("Lottery Not Completed")]
+    LotteryNotCompleted,
+    #[msg("Incorrect Randomness Account")]
+    IncorrectRandomnessAccount,
+    #[msg("Randomness Not Resolved")]
+    RandomnessNotResolved,
+    #[msg("Winner Already Chosen")]
+    WinnerChosen,
+    #[msg("Ticket Is Incorrect")]
+    IncorrectTicket,
+    #[msg("Winner Not Chosen")]
+    WinnerNotChosen,
+    #[msg("Ticket Not Verified")]
+    NotVerifiedTicket,
+    #[msg("Invalid Ticket Accounts")]
+    InvalidTicketAccounts,
+    #[msg("Winning Ticket Can't Be Burned")]
+    WinningTicket,
+    #[msg("Wallet Ticket Limit Reached")]
+    WalletTicketLimit,
+    #[msg("Wallet Not Allowlisted For Presale")]
+    NotAllowlisted,
+    #[msg("Entry Requirements Not Met")]
+    EntryGateNotMet,
+    #[msg("Can't Refer Yourself")]
+    SelfReferral,
+    #[msg("Basis Points Exceed 10000")]
+    InvalidBasisPoints,
+    #[msg("Nothing To Claim")]
+    NothingToClaim,
+    #[msg("Amount Must Be Greater Than Zero")]
+    InvalidAmount,
+    #[msg("Round Cancelled")]
+    RoundCancelled,
+    #[msg("Round Not Cancelled")]
+    RoundNotCancelled,
+    #[msg("No Tickets Sold")]
+    NoTicketsSold,
+    #[msg("Claim Deadline Passed")]
+    ClaimDeadlinePassed,
+    #[msg("Claim Window Still Open")]
+    ClaimWindowOpen,
+    #[msg("Claim Window Must Be Positive")]
+    InvalidClaimWindow,
+    #[msg("Invalid Sweep Destination")]
+    InvalidSweepDestination,
+    #[msg("Prize Already Claimed")]
+    AlreadyClaimed,
+    #[msg("Invalid Vesting Schedule")]
+    InvalidVestingSchedule,
+    #[msg("Vesting Record Account Missing")]
+    VestingRecordMissing,
+    #[msg("Too Many Beneficiaries")]
+    TooManyBeneficiaries,
+    #[msg("Invalid Beneficiary Accounts")]
+    InvalidBeneficiaryAccounts,
+    #[msg("Beneficiaries Not Paid Yet")]
+    BeneficiariesNotPaid,
+    #[msg("Beneficiaries Already Paid")]
+    BeneficiariesAlreadyPaid,
+    #[msg("Previous Round Not Settled")]
+    RoundNotSettled,
+    #[msg("Invalid Round Window")]
+    InvalidRoundWindow,
+    #[msg("Invalid Ticket Price")]
+    InvalidTicketPrice,
+    #[msg("Config Change Not Allowed")]
+    ConfigChangeNotAllowed,
+    #[msg("No Pending Authority")]
+    NoPendingAuthority,
+    #[msg("Lottery Is Paused")]
+    LotteryPaused,
+    #[msg("Invalid Timelock")]
+    InvalidTimelock,
+    #[msg("Timelock Not Elapsed")]
+    TimelockNotElapsed,
+    #[msg("No Round Schedule Set")]
+    NoRoundSchedule,
+    #[msg("Round Below Minimum Participation")]
+    BelowMinimumParticipation,
+    #[msg("Round Met Minimum Participation")]
+    MinimumParticipationMet,
+    #[msg("Round Sold Out")]
+    SoldOut,
+    #[msg("Invalid Weight")]
+    InvalidWeight,
+    #[msg("Weight Index Does Not Match Tickets")]
+    InvalidWeightIndex,
+    #[msg("Weighted Rounds Need A Ticket Cap")]
+    WeightedTicketCap,
+    #[msg("Round Has Unsettled Prizes")]
+    PrizesOutstanding,
+    #[msg("Prize Can Still Be Claimed")]
+    PrizeStillClaimable,
+    #[msg("Round Tickets Still Frozen")]
+    TicketsStillFrozen,
+    #[msg("Refund Window Closed")]
+    RefundWindowClosed,
+    #[msg("Refund Window Still Open")]
+    RefundWindowOpen,
+    #[msg("Round Refunds Not Settled")]
+    RefundsOutstanding,
+    #[msg("Unclaimed Prize Not Swept")]
+    UnclaimedNotSwept,
+    #[msg("Invalid Referral Accounts")]
+    InvalidReferralAccounts,
+    #[msg("Draw Already Requested")]
+    DrawRequested,
+    #[msg("Round Tickets Not Frozen")]
+    TicketsNotFrozen,
+    #[msg("Ticket Can't Be Frozen")]
+    TicketNotFreezable,
+}
 
-    // ticket_mint is now round-scoped + ticket index to avoid collisions
-    #[account(
-        init,
-        payer=payer,
-        seeds=[token_lottery.round_id.to_le_bytes().as_ref(), token_lottery.total_tickets.to_le_bytes().as_ref()],
-        bump,
-        mint::decimals=0,
-        mint::authority=collection_mint,
-        mint::freeze_authority=collection_mint,
-        mint::token_program=token_program
-    )]
-    pub ticket_mint: Box<InterfaceAccount<'info, Mint>>,
+#[event]
+pub struct InitializedConfig {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub price: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+#[event]
+pub struct InitializedLottery {
+    pub collection_mint: Pubkey,
+}
 
-    #[account(
-        mut, 
-        seeds=[b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref()],
-        bump,
-        seeds::program=token_metadata_program.key()
-    )]
-    ///CHECK: These are checked by the token metadata program
-    pub ticket_metadata: UncheckedAccount<'info>,
+#[event]
+pub struct BoughtTicket {
+    pub price: u64,
+    pub current_total_tickets: u64,
+    pub weight: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+#[event]
+pub struct SelectWinner {
+    pub winner: u64,
+    pub winner_chosen: bool,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+#[event]
+pub struct WinningsClaimed {
+    pub round_id: u64,
+    pub ticket_name: String,
+    pub ticket_mint: Pubkey,
+    pub destination_account: Pubkey,
+    // holder of the winning ticket, who gets paid
+    pub claimant: Pubkey,
+    // signer of the claim, the claimant or a payout_winner caller
+    pub caller: Pubkey,
+    pub recipient_token_account: Pubkey,
+    pub amount: u64,
+}
 
-    #[account(
-        init,
-        payer=payer,
-        associated_token::mint=ticket_mint,
-        associated_token::authority=payer,
-        associated_token::token_program=token_program
-    )]
-    pub destination: Box<InterfaceAccount<'info, TokenAccount>>,
+#[event]
+pub struct WinnerCommited {
+    pub oracle_queue: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut, 
-        seeds=[b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref()],
-        bump,
-        seeds::program=token_metadata_program.key()
-    )]
-    ///CHECK: These are checked by the token metadata program
-    pub collection_metadata: UncheckedAccount<'info>,
+#[event]
+pub struct TicketsFrozen {
+    pub round_id: u64,
+    pub frozen: u64,
+    // tickets frozen or burned so far, the draw waits for all of them
+    pub freeze_progress: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut, 
-        seeds=[b"metadata", token_metadata_program.key().as_ref(), ticket_mint.key().as_ref(), b"edition"], 
-        bump, 
-        seeds::program=token_metadata_program
-    )]
-    ///CHECK: These are checked by the token metadata program
-    pub ticket_master_edition: UncheckedAccount<'info>,
+#[event]
+pub struct TicketsThawed {
+    pub round_id: u64,
+    pub thawed: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut, 
-        seeds=[b"metadata", token_metadata_program.key().as_ref(), collection_mint.key().as_ref(), b"edition"], 
-        bump, 
-        seeds::program=token_metadata_program
-    )]
-    ///CHECK: These are checked by the token metadata program
-    pub collection_master_edition: UncheckedAccount<'info>,
+#[event]
+pub struct TicketBurned {
+    pub round_id: u64,
+    pub ticket_index: u64,
+    pub owner: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TicketCapsUpdated {
+    pub max_tickets_per_wallet: u64,
+    pub max_total_tickets: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PresaleUpdated {
+    pub merkle_root: [u8; 32],
+    pub presale_end_time: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct EntryGateUpdated {
+    pub entry_gate: EntryGate,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferralBpsUpdated {
+    pub referral_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferrerRegistered {
+    pub referrer: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferralCredited {
+    pub round_id: u64,
+    pub referrer: Pubkey,
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub tickets_referred: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferralRewardsClaimed {
+    pub round_id: u64,
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub total_claimed: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PotFunded {
+    pub round_id: u64,
+    pub sponsor: Pubkey,
+    pub amount: u64,
+    pub pot_amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RoundCancelled {
+    pub round_id: u64,
+    pub pot_amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SponsorRefunded {
+    pub round_id: u64,
+    pub sponsor: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PotRolledOver {
+    pub from_round_id: u64,
+    pub to_round_id: u64,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ClaimPolicyUpdated {
+    pub claim_window: i64,
+    pub unclaimed_policy: UnclaimedPolicy,
+    pub treasury: Pubkey,
+    pub charity: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct VestingScheduleUpdated {
+    pub vesting: Option<VestingSchedule>,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct WinningsVested {
+    pub round_id: u64,
+    pub beneficiary: Pubkey,
+    pub total_amount: u64,
+    pub start_time: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct VestedWithdrawn {
+    pub round_id: u64,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub released_amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RolesUpdated {
+    pub operator: Pubkey,
+    pub treasurer: Pubkey,
+    pub pauser: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct MinParticipationUpdated {
+    pub min_tickets: u64,
+    pub min_pot: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RefundModeEntered {
+    pub round_id: u64,
+    pub total_tickets: u64,
+    pub pot_amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TicketRefunded {
+    pub round_id: u64,
+    pub ticket_index: u64,
+    pub holder: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RefundsClosed {
+    pub round_id: u64,
+    pub to_round_id: u64,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RoundScheduleUpdated {
+    pub schedule: Option<RoundSchedule>,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ConfigChangeQueued {
+    pub change: ConfigChange,
+    pub executable_at: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ConfigChangeExecuted {
+    pub change: ConfigChange,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    pub token_metadata_program: Program<'info, Metadata>,
+#[event]
+pub struct ConfigChangeCancelled {
+    pub change: ConfigChange,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    // collection_mint is round-scoped
-    #[account(
-        mut,
-        seeds=[b"collection_mint".as_ref(), token_lottery.round_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
+#[event]
+pub struct PauseUpdated {
+    pub sales_paused: bool,
+    pub claims_paused: bool,
+    pub signer: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        seeds = [b"metadata", token_metadata_program.key().as_ref(), 
-        ticket_mint.key().as_ref()],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    /// CHECK: This account will be initialized by the metaplex program
-    pub metadata: UncheckedAccount<'info>,
+#[event]
+pub struct AuthorityProposed {
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        seeds = [b"metadata", token_metadata_program.key().as_ref(), 
-            ticket_mint.key().as_ref(), b"edition"],
-        bump,
-        seeds::program = token_metadata_program.key(),
-    )]
-    /// CHECK: This account will be initialized by the metaplex program
-    pub master_edition: UncheckedAccount<'info>,
+#[event]
+pub struct AuthorityTransferred {
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_program: Interface<'info, TokenInterface>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct AuthorityTransferCancelled {
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-// ---------------------------- Errors & Events & State ---------------------------- //
+#[event]
+pub struct ConfigUpdated {
+    pub round_id: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub ticket_price: u64,
+    pub max_tickets_per_wallet: u64,
+    pub max_total_tickets: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Lottery is not open yet.")]
-    LotteryNotOpen,
-    #[msg("You're Not Authorized!.")]
-    NotAuthorized,
-    #[msg("Randomnes Already Revealed")]
-    RandomnessAlreadyRevealed,
-    #[msg("Lottery Not Completed")]
-    LotteryNotCompleted,
-    #[msg("Incorrect Randomness Account")]
-    IncorrectRandomnessAccount,
-    #[msg("Randomness Not Resolved")]
-    RandomnessNotResolved,
-    #[msg("Winner Already Chosen")]
-    WinnerChosen,
-    #[msg("Ticket Is Incorrect")]
-    IncorrectTicket,
-    #[msg("Winner Not Chosen")]
-    WinnerNotChosen,
-    #[msg("Ticket Not Verified")]
-    NotVerifiedTicket,
+#[event]
+pub struct RoundStarted {
+    pub round_id: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub ticket_price: u64,
+    pub rollover_amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
-pub struct InitializedConfig {
+pub struct WeightIndexClosed {
+    pub round_id: u64,
+    pub payer: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct WeightBonusUpdated {
+    pub weight_bonus: WeightBonus,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct WindowModeUpdated {
+    pub window_mode: WindowMode,
     pub start_time: i64,
     pub end_time: i64,
-    pub price: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
+
 #[event]
-pub struct InitializedLottery {
-    pub collection_mint: Pubkey,
+pub struct BeneficiariesUpdated {
+    pub beneficiaries: Vec<Beneficiary>,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
-pub struct BoughtTicket {
-    pub price: u64,
-    pub current_total_tickets: u64,
+pub struct BeneficiaryPaid {
+    pub round_id: u64,
+    pub beneficiary: Pubkey,
+    pub bps: u16,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
+
 #[event]
-pub struct SelectWinner {
-    pub winner: u64,
-    pub winner_chosen: bool,
+pub struct PrizeDeposited {
+    pub round_id: u64,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub total_amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
+
 #[event]
-pub struct WinningsClaimed {
-    pub ticket_name: String,
-    pub destination_account: Pubkey,
+pub struct PrizeClaimed {
+    pub round_id: u64,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub winner: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
-pub struct WinnerCommited {
-    pub oracle_queue: Pubkey,
+pub struct PrizeWithdrawn {
+    pub round_id: u64,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct UnclaimedPrizeSwept {
+    pub round_id: u64,
+    pub unclaimed_policy: UnclaimedPolicy,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[account]
@@ -841,6 +4840,477 @@ pub struct TokenLottery {
     pub ticket_price: u64,
     pub authority: Pubkey,
     pub bump: u8,
+    pub max_tickets_per_wallet: u64,
+    pub max_total_tickets: u64,
+    pub merkle_root: [u8; 32],
+    pub presale_end_time: i64,
+    pub entry_gate: EntryGate,
+    pub token_mint: Pubkey,
+    pub referral_bps: u16,
+    pub sponsored_amount: u64,
+    pub cancelled: bool,
+    // seconds the winner has to claim after the draw, always positive
+    pub claim_window: i64,
+    // 0 until the round is drawn
+    pub claim_deadline: i64,
+    pub unclaimed_policy: UnclaimedPolicy,
+    pub treasury: Pubkey,
+    pub charity: Pubkey,
+    // when set, claim_winnings vests the pot instead of paying it out at once
+    pub vesting: Option<VestingSchedule>,
+    // share of each round's pot paid out before the winner can claim
+    #[max_len(5)]
+    pub beneficiaries: Vec<Beneficiary>,
+    pub beneficiaries_paid: bool,
+    // set by propose_authority until the new key accepts
+    pub pending_authority: Option<Pubkey>,
+    // starts, restarts and draws rounds
+    pub operator: Pubkey,
+    // sweeps and withdrawals out of the vault
+    pub treasurer: Pubkey,
+    // emergency stop
+    pub pauser: Pubkey,
+    // ticket sales and sponsor deposits
+    pub sales_paused: bool,
+    // every payout out of the vault
+    pub claims_paused: bool,
+    pub oracle_queue: Pubkey,
+    // delay in seconds before a queued config change can be executed
+    pub config_timelock: i64,
+    // lets start_next_round open rounds without the operator
+    pub schedule: Option<RoundSchedule>,
+    // rounds closing below either threshold are refunded instead of drawn
+    pub min_tickets: u64,
+    pub min_pot: u64,
+    // unit of start_time, end_time, presale_end_time and the round schedule
+    pub window_mode: WindowMode,
+    pub weight_bonus: WeightBonus,
+    // this round's ticket sales after referral credits, what a cancellation takes from the pot
+    pub ticket_sales: u64,
+    // when claims were last paused, the claim deadline is pushed back by the pause on resume
+    pub claims_paused_at: i64,
+    // set by commit_winner, the round's window and caps are final from then on
+    pub draw_requested: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigUpdate {
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub max_tickets_per_wallet: Option<u64>,
+    pub max_total_tickets: Option<u64>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TicketRefund {
+    pub round_id: u64,
+    pub ticket_index: u64,
+    pub holder: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+// running total of ticket weights for one round, cumulative[i] covers tickets 0..=i and only
+// the first len entries are used
+#[account(zero_copy)]
+pub struct WeightIndex {
+    pub round_id: u64,
+    pub total_weight: u64,
+    pub len: u64,
+    // paid the rent, gets it back from close_weight_index
+    pub payer: Pubkey,
+    pub cumulative: [u64; MAX_WEIGHTED_TICKETS as usize],
+    pub bump: u8,
+    pub _padding: [u8; 7],
+}
+
+// launch_end_time is in the lottery's window_mode unit, weights of 1 mean no bonus
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct WeightBonus {
+    pub launch_end_time: i64,
+    pub launch_weight: u32,
+    pub partner_mint: Pubkey,
+    pub partner_min_amount: u64,
+    pub partner_weight: u32,
+}
+
+impl WeightBonus {
+    pub fn is_weighted(&self) -> bool {
+        self.launch_weight > 1 || self.partner_weight > 1
+    }
+}
+
+impl Default for WeightBonus {
+    fn default() -> Self {
+        WeightBonus {
+            launch_end_time: 0,
+            launch_weight: 1,
+            partner_mint: Pubkey::default(),
+            partner_min_amount: 0,
+            partner_weight: 1,
+        }
+    }
+}
+
+// claim deadlines, vesting and the config timelock always use unix timestamps
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum WindowMode {
+    Timestamp,
+    Slot,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct RoundSchedule {
+    pub round_duration: i64,
+    // time between one round's end and the next one's start, in the window_mode unit
+    pub gap: i64,
+    pub ticket_price: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PendingConfigChange {
+    pub proposer: Pubkey,
+    pub change: ConfigChange,
+    pub executable_at: i64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum ConfigChange {
+    TicketPrice(u64),
+    ReferralBps(u16),
+    Beneficiaries(#[max_len(5)] Vec<Beneficiary>),
+    OracleQueue(Pubkey),
+    Timelock(i64),
+    Vesting(Option<VestingSchedule>),
+    ClaimPolicy(ClaimPolicy),
+    Schedule(Option<RoundSchedule>),
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct ClaimPolicy {
+    // seconds the winner has to claim after the draw, must be positive
+    pub claim_window: i64,
+    pub unclaimed_policy: UnclaimedPolicy,
+    pub treasury: Pubkey,
+    pub charity: Pubkey,
+}
+
+impl TokenLottery {
+    pub fn beneficiaries_settled(&self) -> bool {
+        self.beneficiaries.is_empty() || self.beneficiaries_paid
+    }
+
+    // current position in the sale window, in the unit of window_mode
+    pub fn window_now(&self, clock: &Clock) -> i64 {
+        match self.window_mode {
+            WindowMode::Timestamp => clock.unix_timestamp,
+            WindowMode::Slot => clock.slot as i64,
+        }
+    }
+
+    // the weight index has a fixed capacity, so weighted rounds need a cap that fits in it
+    pub fn weighted_cap_ok(&self) -> bool {
+        !self.weight_bonus.is_weighted()
+            || (1..=MAX_WEIGHTED_TICKETS).contains(&self.max_total_tickets)
+    }
+
+    // 0 = no cap
+    pub fn sold_out(&self) -> bool {
+        self.max_total_tickets != 0 && self.total_tickets >= self.max_total_tickets
+    }
+
+    // a sold out round can be drawn without waiting for end_time
+    pub fn sales_closed(&self, now: i64) -> bool {
+        now >= self.end_time || self.sold_out()
+    }
+
+    // 0 = not drawn yet, and the deadline doesn't run out while claims are paused
+    pub fn claim_deadline_passed(&self, now: i64) -> bool {
+        self.claim_deadline != 0 && !self.claims_paused && now > self.claim_deadline
+    }
+
+    pub fn meets_min_participation(&self) -> bool {
+        self.total_tickets >= self.min_tickets && self.pot_amount >= self.min_pot
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct Beneficiary {
+    pub wallet: Pubkey,
+    pub bps: u16,
+}
+
+// instalments unlock evenly over duration, nothing before cliff (seconds from the claim)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct VestingSchedule {
+    pub cliff: i64,
+    pub duration: i64,
+    pub instalments: u32,
+}
+
+// where sweep_unclaimed sends a prize nobody claimed in time
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum UnclaimedPolicy {
+    Rollover,
+    Treasury,
+    Charity,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum EntryGate {
+    None,
+    // holders of an NFT from a verified collection
+    Collection { mint: Pubkey },
+    // holders of at least min_amount of a token
+    Token { mint: Pubkey, min_amount: u64 },
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct RoundHistory {
+    pub round_id: u64,
+    pub collection_mint: Pubkey,
+    pub total_tickets: u64,
+    pub pot_amount: u64,
+    pub winner: u64,
+    pub winner_chosen: bool,
+    pub cancelled: bool,
+    // pot carried into the next round by restart_lottery
+    pub rollover_amount: u64,
+    pub claimed: bool,
+    pub claimant: Pubkey,
+    pub claimed_amount: u64,
+    pub claimed_at: i64,
+    pub bump: u8,
+    pub total_weight: u64,
+    // escrowed prizes not yet claimed or withdrawn, the round can't be closed before they are
+    pub outstanding_prizes: u64,
+    // tickets frozen for the draw and not thawed yet
+    pub frozen_tickets: u64,
+    // tickets 0..freeze_progress have been frozen (or burned) for the draw
+    pub freeze_progress: u64,
+    pub ticket_price: u64,
+    // set when the round is cancelled: each ticket's refund, the ticket and sponsor funds held
+    // back from the pot for refunds, and when close_refunds can release what's left of them
+    pub refund_amount: u64,
+    pub refund_pool: u64,
+    pub refund_deadline: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct VestingRecord {
+    pub round_id: u64,
+    pub beneficiary: Pubkey,
+    pub total_amount: u64,
+    pub released_amount: u64,
+    pub start_time: i64,
+    pub schedule: VestingSchedule,
+    pub bump: u8,
+}
+
+impl VestingRecord {
+    pub fn unlocked_amount(&self, now: i64) -> u64 {
+        let elapsed = now.saturating_sub(self.start_time);
+        if elapsed < self.schedule.cliff {
+            return 0;
+        }
+        if elapsed >= self.schedule.duration {
+            return self.total_amount;
+        }
+
+        let instalments = self.schedule.instalments as u128;
+        let unlocked_instalments = (elapsed as u128 * instalments) / self.schedule.duration as u128;
+        (self.total_amount as u128 * unlocked_instalments / instalments) as u64
+    }
+}
+
+// NFT or token amount escrowed for the winner of a round, on top of the pot
+#[account]
+#[derive(InitSpace)]
+pub struct RoundPrize {
+    pub round_id: u64,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub claimed: bool,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SponsorContribution {
+    pub round_id: u64,
+    pub sponsor: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ReferralAccrual {
+    pub referrer: Pubkey,
+    pub claimed: u64,
+    pub tickets_referred: u64,
+    pub bump: u8,
+}
+
+// a referrer's credits from one round, claimable once that round is drawn
+#[account]
+#[derive(InitSpace)]
+pub struct RoundReferral {
+    pub round_id: u64,
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PlayerEntry {
+    pub round_id: u64,
+    pub player: Pubkey,
+    pub tickets: u64,
+    pub bump: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        if a <= b {
+            hash(&[a, b].concat()).to_bytes()
+        } else {
+            hash(&[b, a].concat()).to_bytes()
+        }
+    }
+
+    // same leaf buy_ticket builds for a presale allocation
+    fn leaf(wallet: &Pubkey, allocation: u64) -> [u8; 32] {
+        hash(&[wallet.as_ref(), &allocation.to_le_bytes()].concat()).to_bytes()
+    }
+
+    #[test]
+    fn merkle_proof_accepts_every_leaf_of_the_tree() {
+        let wallets: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let leaves: Vec<[u8; 32]> = wallets.iter().map(|wallet| leaf(wallet, 2)).collect();
+        let left = hash_pair(leaves[0], leaves[1]);
+        let right = hash_pair(leaves[2], leaves[3]);
+        let root = hash_pair(left, right);
+
+        assert!(verify_merkle_proof(&[leaves[1], right], root, leaves[0]));
+        assert!(verify_merkle_proof(&[leaves[0], right], root, leaves[1]));
+        assert!(verify_merkle_proof(&[leaves[3], left], root, leaves[2]));
+        assert!(verify_merkle_proof(&[leaves[2], left], root, leaves[3]));
+    }
+
+    #[test]
+    fn merkle_proof_rejects_outsiders_and_bad_proofs() {
+        let wallet = Pubkey::new_unique();
+        let leaves = [leaf(&wallet, 2), leaf(&Pubkey::new_unique(), 2)];
+        let root = hash_pair(leaves[0], leaves[1]);
+        let outsider = leaf(&Pubkey::new_unique(), 2);
+
+        // the allocation is part of the leaf, so it can't be inflated
+        assert!(!verify_merkle_proof(&[leaves[1]], root, leaf(&wallet, 3)));
+        assert!(!verify_merkle_proof(&[leaves[1]], root, outsider));
+        assert!(!verify_merkle_proof(&[outsider], root, leaves[0]));
+        assert!(!verify_merkle_proof(&[], root, leaves[0]));
+        // a single leaf tree is its own root
+        assert!(verify_merkle_proof(&[], leaves[0], leaves[0]));
+    }
+
+    fn vesting(total_amount: u64, cliff: i64, duration: i64, instalments: u32) -> VestingRecord {
+        VestingRecord {
+            round_id: 0,
+            beneficiary: Pubkey::new_unique(),
+            total_amount,
+            released_amount: 0,
+            start_time: 1_000,
+            schedule: VestingSchedule {
+                cliff,
+                duration,
+                instalments,
+            },
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn vesting_unlocks_nothing_before_the_cliff() {
+        let record = vesting(1_000, 100, 400, 4);
+        assert_eq!(record.unlocked_amount(0), 0);
+        assert_eq!(record.unlocked_amount(1_000), 0);
+        assert_eq!(record.unlocked_amount(1_099), 0);
+    }
+
+    #[test]
+    fn vesting_unlocks_whole_instalments() {
+        let record = vesting(1_000, 100, 400, 4);
+        assert_eq!(record.unlocked_amount(1_100), 250);
+        assert_eq!(record.unlocked_amount(1_199), 250);
+        assert_eq!(record.unlocked_amount(1_200), 500);
+        assert_eq!(record.unlocked_amount(1_399), 750);
+        assert_eq!(record.unlocked_amount(1_400), 1_000);
+        assert_eq!(record.unlocked_amount(i64::MAX), 1_000);
+    }
+
+    #[test]
+    fn vesting_rounds_down_and_pays_the_dust_at_the_end() {
+        let record = vesting(1_000, 0, 300, 3);
+        assert_eq!(record.unlocked_amount(1_100), 333);
+        assert_eq!(record.unlocked_amount(1_200), 666);
+        assert_eq!(record.unlocked_amount(1_300), 1_000);
+    }
+
+    #[test]
+    fn cap_changes_can_only_raise_or_lift_the_cap() {
+        assert!(validate_cap_change(10, 10, 5).is_ok());
+        assert!(validate_cap_change(10, 20, 5).is_ok());
+        assert!(validate_cap_change(10, 0, 5).is_ok());
+        assert!(validate_cap_change(0, 0, 5).is_ok());
+        assert!(validate_cap_change(10, 9, 5).is_err());
+        // adding a cap to an uncapped round is a cut
+        assert!(validate_cap_change(0, 100, 5).is_err());
+    }
+
+    #[test]
+    fn cap_changes_cannot_go_below_tickets_sold() {
+        assert!(validate_cap_change(10, 12, 12).is_ok());
+        assert!(validate_cap_change(10, 11, 12).is_err());
+    }
+
+    #[test]
+    fn weighted_draw_maps_each_target_to_its_ticket() {
+        // weights 1, 3, 1 -> targets 0 | 1 2 3 | 4
+        let cumulative = [1, 4, 5];
+        let winners: Vec<u64> = (0..10)
+            .map(|randomness| pick_weighted_winner(&cumulative, randomness))
+            .collect();
+        assert_eq!(winners, [0, 1, 1, 1, 2, 0, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn weighted_draw_uses_all_128_bits() {
+        let cumulative = [1, 2];
+        assert_eq!(pick_weighted_winner(&cumulative, 1u128 << 64), 0);
+        assert_eq!(pick_weighted_winner(&cumulative, (1u128 << 64) + 1), 1);
+        assert_eq!(pick_weighted_winner(&cumulative, u128::MAX), 1);
+    }
+
+    #[test]
+    fn weighted_draw_with_equal_weights_is_uniform() {
+        let cumulative: Vec<u64> = (1..=4).collect();
+        for randomness in 0..8u128 {
+            assert_eq!(
+                pick_weighted_winner(&cumulative, randomness),
+                (randomness % 4) as u64
+            );
+        }
+    }
 }
 
 // use anchor_lang::prelude::*;
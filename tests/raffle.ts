@@ -2,8 +2,11 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Raffle } from "../target/types/raffle";
 import { TOKEN_PROGRAM_ID } from "@coral-xyz/anchor/dist/cjs/utils/token";
+import { expect } from "chai";
+import { createHash } from "crypto";
 import {
   createMint,
+  getAccount,
   getAssociatedTokenAddressSync,
   getOrCreateAssociatedTokenAccount,
   mintTo,
@@ -75,6 +78,7 @@ describe("token-lottery full cycle", () => {
 
     const initConfigIx = await program.methods
       .initializeConfig(new anchor.BN(startTime), new anchor.BN(endTime), new anchor.BN(10000))
+      .accounts({ tokenMint })
       .instruction();
 
     const { blockhash, lastValidBlockHeight } = await connection.getLatestBlockhash();
@@ -90,6 +94,177 @@ describe("token-lottery full cycle", () => {
     console.log("✅ Initialized config (one-time setup)");
   });
 
+  async function tokenBalance(tokenAccount: anchor.web3.PublicKey): Promise<bigint> {
+    return (await getAccount(connection, tokenAccount)).amount;
+  }
+
+  async function expectAnchorError(promise: Promise<unknown>, code: string) {
+    let caught: unknown;
+    try {
+      await promise;
+    } catch (err) {
+      caught = err;
+    }
+    expect(caught, `expected ${code}`).to.be.instanceOf(anchor.AnchorError);
+    expect((caught as anchor.AnchorError).error.errorCode.code).to.equal(code);
+  }
+
+  function roundHistoryPda(roundId: anchor.BN): anchor.web3.PublicKey {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("round_history"), roundId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  }
+
+  async function sendWithBudget(...ixs: anchor.web3.TransactionInstruction[]) {
+    const tx = new anchor.web3.Transaction()
+      .add(...ixs)
+      .add(anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 300_000 }))
+      .add(anchor.web3.ComputeBudgetProgram.setComputeUnitPrice({ microLamports: 1 }));
+    await anchor.web3.sendAndConfirmTransaction(connection, tx, [wallet.payer]);
+  }
+
+  // restarts the lottery with a window open from now until endIn seconds and mints the new
+  // round's collection, returns the new round id
+  async function openRound(endIn: number): Promise<anchor.BN> {
+    const now = Math.floor(Date.now() / 1000);
+    const restartIx = await program.methods
+      .restartLottery(new anchor.BN(now - 10), new anchor.BN(now + endIn), new anchor.BN(10000))
+      .accounts({
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        operator: wallet.publicKey,
+      })
+      .instruction();
+    await sendWithBudget(restartIx);
+
+    const { roundId } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    const [collectionMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("collection_mint"), roundId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [metadata] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), collectionMint.toBuffer()],
+      TOKEN_METADATA_PROGRAM_ID
+    );
+    const [masterEdition] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), collectionMint.toBuffer(), Buffer.from("edition")],
+      TOKEN_METADATA_PROGRAM_ID
+    );
+    const initLotteryIx = await program.methods
+      .initializeLottery()
+      .accounts({
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        masterEdition,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        collectionMint,
+        collectionTokenAccount: getAssociatedTokenAddressSync(collectionMint, collectionMint, true),
+        metadata,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+    await sendWithBudget(initLotteryIx);
+    return roundId;
+  }
+
+  function referralAccrualPda(referrer: anchor.web3.PublicKey): anchor.web3.PublicKey {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("referral"), referrer.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  function roundReferralPda(roundId: anchor.BN, referrer: anchor.web3.PublicKey): anchor.web3.PublicKey {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("round_referral"), roundId.toArrayLike(Buffer, "le", 8), referrer.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  // buys the current round's next ticket, crediting referrer, showing the gate token account and
+  // proving the presale allocation if given
+  async function buyTicket(
+    opts: {
+      referrer?: anchor.web3.PublicKey;
+      gateTokenAccount?: anchor.web3.PublicKey;
+      proof?: number[][];
+      allocation?: number;
+    } = {}
+  ) {
+    const { roundId } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    await program.methods
+      .buyTicket(opts.proof ?? [], new anchor.BN(opts.allocation ?? 0))
+      .accounts({
+        payer: wallet.publicKey,
+        payerTokenAccount: userTokenAccount,
+        //@ts-ignore
+        raffleVaultAccount: vaultTokenAccount,
+        tokenMint,
+        tokenLottery: tokenLotteryPda,
+        referralAccrual: opts.referrer ? referralAccrualPda(opts.referrer) : null,
+        roundReferral: opts.referrer ? roundReferralPda(roundId, opts.referrer) : null,
+        weightIndex: null,
+        gateTokenAccount: opts.gateTokenAccount ?? null,
+        gateMetadata: null,
+        partnerTokenAccount: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 300_000 })])
+      .rpc();
+  }
+
+  async function fundedKeypair(): Promise<anchor.web3.Keypair> {
+    const keypair = anchor.web3.Keypair.generate();
+    const tx = new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({
+        fromPubkey: wallet.publicKey,
+        toPubkey: keypair.publicKey,
+        lamports: 0.05 * anchor.web3.LAMPORTS_PER_SOL,
+      })
+    );
+    await anchor.web3.sendAndConfirmTransaction(connection, tx, [wallet.payer]);
+    return keypair;
+  }
+
+  async function cancelRound(roundId: anchor.BN) {
+    await program.methods
+      .cancelRound()
+      .accounts({
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        roundHistory: roundHistoryPda(roundId),
+        authority: wallet.publicKey,
+      })
+      .rpc();
+  }
+
+  function sponsorContributionPda(roundId: anchor.BN): anchor.web3.PublicKey {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("sponsor"), roundId.toArrayLike(Buffer, "le", 8), wallet.publicKey.toBuffer()],
+      program.programId
+    )[0];
+  }
+
+  async function fundPot(roundId: anchor.BN, amount: number) {
+    await program.methods
+      .fundPot(new anchor.BN(amount))
+      .accounts({
+        sponsor: wallet.publicKey,
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        tokenMint,
+        sponsorTokenAccount: userTokenAccount,
+        raffleVaultAccount: vaultTokenAccount,
+        sponsorContribution: sponsorContributionPda(roundId),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+  }
+
   async function runLotteryRound(roundNumber: number) {
     console.log(`\n🎯 Running Lottery Round ${roundNumber}...\n`);
 
@@ -111,7 +286,7 @@ describe("token-lottery full cycle", () => {
         .accounts({
           //@ts-ignore
           tokenLottery: tokenLotteryPda,
-          authority: wallet.publicKey,
+          operator: wallet.publicKey,
         })
         .instruction();
 
@@ -187,7 +362,7 @@ describe("token-lottery full cycle", () => {
 
     // ✅ Buy Ticket
     const buyIx = await program.methods
-      .buyTicket()
+      .buyTicket([], new anchor.BN(0))
       .accounts({
         payer: wallet.publicKey,
         payerTokenAccount: userTokenAccount,
@@ -195,6 +370,12 @@ describe("token-lottery full cycle", () => {
         raffleVaultAccount: vaultTokenAccount,
         tokenMint,
         tokenLottery: tokenLotteryPda,
+        referralAccrual: null,
+        roundReferral: null,
+        weightIndex: null,
+        gateTokenAccount: null,
+        gateMetadata: null,
+        partnerTokenAccount: null,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId,
       })
@@ -208,10 +389,37 @@ describe("token-lottery full cycle", () => {
     console.log("⏳ Waiting for lottery to end...");
     await new Promise((resolve) => setTimeout(resolve, 65000)); // Wait 65 seconds to ensure it's past end_time
 
+    // ✅ Freeze the round's tickets before the draw
+    const [boughtTicketMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [new anchor.BN(roundId).toArrayLike(Buffer, "le", 8), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [boughtTicketEdition] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), boughtTicketMint.toBuffer(), Buffer.from("edition")],
+      TOKEN_METADATA_PROGRAM_ID
+    );
+
+    const freezeTx = await program.methods
+      .freezeTickets()
+      .accounts({
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        collectionMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: boughtTicketMint, isSigner: false, isWritable: false },
+        { pubkey: getAssociatedTokenAddressSync(boughtTicketMint, wallet.publicKey), isSigner: false, isWritable: true },
+        { pubkey: boughtTicketEdition, isSigner: false, isWritable: false },
+      ])
+      .rpc();
+    console.log("🧊 Tickets frozen:", freezeTx);
+
     // ✅ Commit Winner
     const tx3 = await program.methods
       .commitWinner(0)
-      .accounts({ payer: wallet.publicKey })
+      //@ts-ignore
+      .accounts({ payer: wallet.publicKey, weightIndex: null })
       .rpc();
     console.log("🎲 Winner committed:", tx3);
 
@@ -244,14 +452,96 @@ describe("token-lottery full cycle", () => {
         tokenLottery: tokenLotteryPda,
         rewardMint: tokenMint,
         rewardVault: vaultTokenAccount,
+        vestingRecord: null,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId,
       })
       .instruction();
 
-    const tx4 = new anchor.web3.Transaction().add(claimIx);
-    await anchor.web3.sendAndConfirmTransaction(connection, tx4, [wallet.payer]);
-    console.log("🏆 Prize claimed successfully!");
+    // ✅ The pot can't be swept while the winner can still claim it
+    await expectAnchorError(
+      program.methods
+        .sweepUnclaimed()
+        .accounts({
+          treasurer: wallet.publicKey,
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          roundHistory: roundHistoryPda(roundId),
+          tokenMint,
+          raffleVaultAccount: vaultTokenAccount,
+          destinationTokenAccount: userTokenAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc(),
+      "ClaimWindowOpen"
+    );
+
+    const potAmount = BigInt(tokenLotteryUpdated.potAmount.toString());
+    const winnerBalanceBefore = await tokenBalance(userTokenAccount);
+    const vaultBalanceBefore = await tokenBalance(vaultTokenAccount);
+
+    if (roundNumber < 2) {
+      const tx4 = new anchor.web3.Transaction().add(claimIx);
+      await anchor.web3.sendAndConfirmTransaction(connection, tx4, [wallet.payer]);
+      console.log("🏆 Prize claimed successfully!");
+    } else {
+      // anyone can push the pot to the winning ticket's holder, the holder doesn't sign
+      const caller = anchor.web3.Keypair.generate();
+      const fundCallerTx = new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.transfer({
+          fromPubkey: wallet.publicKey,
+          toPubkey: caller.publicKey,
+          lamports: 0.05 * anchor.web3.LAMPORTS_PER_SOL,
+        })
+      );
+      await anchor.web3.sendAndConfirmTransaction(connection, fundCallerTx, [wallet.payer]);
+
+      const payoutIx = await program.methods
+        .payoutWinner()
+        .accounts({
+          caller: caller.publicKey,
+          holder: wallet.publicKey,
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          vestingRecord: null,
+          rewardMint: tokenMint,
+          rewardVault: vaultTokenAccount,
+          holderRewardAccount: userTokenAccount,
+          ticketMint,
+          metadata: ticketMetadata,
+          holderTicketAccount: destination,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .instruction();
+      const txPayout = new anchor.web3.Transaction().add(payoutIx);
+      await anchor.web3.sendAndConfirmTransaction(connection, txPayout, [caller]);
+      console.log("🏆 Prize paid out to the winner!");
+    }
+
+    // ✅ The whole pot moved from the vault to the winner and the claim is on record
+    expect(await tokenBalance(userTokenAccount)).to.equal(winnerBalanceBefore + potAmount);
+    expect(await tokenBalance(vaultTokenAccount)).to.equal(vaultBalanceBefore - potAmount);
+    const claimedLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(claimedLottery.potAmount.toNumber()).to.equal(0);
+    const claimedHistory = await program.account.roundHistory.fetch(roundHistoryPda(roundId));
+    expect(claimedHistory.claimed).to.equal(true);
+    expect(claimedHistory.claimant.toBase58()).to.equal(wallet.publicKey.toBase58());
+    expect(claimedHistory.claimedAmount.toString()).to.equal(potAmount.toString());
+
+    // ✅ Thaw the round's tickets, the next round can't open while any are frozen
+    const thawTx = await program.methods
+      .thawTickets(new anchor.BN(roundId))
+      .accounts({
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: boughtTicketMint, isSigner: false, isWritable: false },
+        { pubkey: getAssociatedTokenAddressSync(boughtTicketMint, wallet.publicKey), isSigner: false, isWritable: true },
+        { pubkey: boughtTicketEdition, isSigner: false, isWritable: false },
+      ])
+      .rpc();
+    console.log("🔥 Tickets thawed:", thawTx);
   }
 
   it("Runs multiple full lottery rounds", async () => {
@@ -259,4 +549,459 @@ describe("token-lottery full cycle", () => {
     await runLotteryRound(1);
     await runLotteryRound(2); // You can run as many rounds as you want!
   });
-});
\ No newline at end of file
+
+  it("Rolls an undrawn round's pot into the next round", async () => {
+    const roundId = await openRound(20);
+    await fundPot(roundId, 5000);
+    const vaultBalance = await tokenBalance(vaultTokenAccount);
+
+    // nobody bought a ticket, so once the window closes the sponsored pot carries over
+    await new Promise((resolve) => setTimeout(resolve, 25000));
+    const nextRoundId = await openRound(60);
+    expect(nextRoundId.toNumber()).to.equal(roundId.toNumber() + 1);
+
+    const history = await program.account.roundHistory.fetch(roundHistoryPda(roundId));
+    expect(history.rolloverAmount.toNumber()).to.equal(5000);
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.potAmount.toNumber()).to.equal(5000);
+    expect(tokenLottery.sponsoredAmount.toNumber()).to.equal(0);
+    expect(await tokenBalance(vaultTokenAccount)).to.equal(vaultBalance);
+  });
+
+  it("Refunds a cancelled round's tickets and sponsors exactly", async () => {
+    // the round opened by the rollover test already holds 5000 carried over
+    const { roundId, potAmount: rolledOver } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    await buyTicket();
+    await buyTicket();
+    await fundPot(roundId, 3000);
+    const vaultBalance = await tokenBalance(vaultTokenAccount);
+
+    await program.methods
+      .cancelRound()
+      .accounts({
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        roundHistory: roundHistoryPda(roundId),
+        authority: wallet.publicKey,
+      })
+      .rpc();
+
+    // the sales and the sponsor's deposit are held back, the carried over pot isn't refundable
+    let history = await program.account.roundHistory.fetch(roundHistoryPda(roundId));
+    expect(history.cancelled).to.equal(true);
+    expect(history.refundAmount.toNumber()).to.equal(10000);
+    expect(history.refundPool.toNumber()).to.equal(2 * 10000 + 3000);
+    let tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.potAmount.toNumber()).to.equal(rolledOver.toNumber());
+
+    const refundTicket = (ticketIndex: number) =>
+      program.methods
+        .refundTicket(roundId, new anchor.BN(ticketIndex))
+        .accounts({
+          holder: wallet.publicKey,
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          roundHistory: roundHistoryPda(roundId),
+          tokenMint,
+          raffleVaultAccount: vaultTokenAccount,
+          holderTokenAccount: userTokenAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    for (const ticketIndex of [0, 1]) {
+      const before = await tokenBalance(userTokenAccount);
+      await refundTicket(ticketIndex);
+      expect(await tokenBalance(userTokenAccount)).to.equal(before + BigInt(10000));
+    }
+
+    // each ticket is refunded once
+    let refundedTwice = true;
+    try {
+      await refundTicket(0);
+    } catch {
+      refundedTwice = false;
+    }
+    expect(refundedTwice).to.equal(false);
+
+    const beforeSponsorRefund = await tokenBalance(userTokenAccount);
+    await program.methods
+      .refundSponsor(roundId)
+      .accounts({
+        sponsor: wallet.publicKey,
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        roundHistory: roundHistoryPda(roundId),
+        sponsorContribution: sponsorContributionPda(roundId),
+        tokenMint,
+        raffleVaultAccount: vaultTokenAccount,
+        sponsorTokenAccount: userTokenAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    expect(await tokenBalance(userTokenAccount)).to.equal(beforeSponsorRefund + BigInt(3000));
+
+    history = await program.account.roundHistory.fetch(roundHistoryPda(roundId));
+    expect(history.refundPool.toNumber()).to.equal(0);
+    const contribution = await program.account.sponsorContribution.fetch(sponsorContributionPda(roundId));
+    expect(contribution.amount.toNumber()).to.equal(0);
+
+    // only the refunds left the vault, the carried over pot is still there
+    expect(await tokenBalance(vaultTokenAccount)).to.equal(vaultBalance - BigInt(2 * 10000 + 3000));
+    tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.potAmount.toNumber()).to.equal(rolledOver.toNumber());
+  });
+
+  it("Credits a registered referrer per round and rejects self-referral", async () => {
+    const roundId = await openRound(60);
+    const referrer = await fundedKeypair();
+    await program.methods
+      .registerReferrer()
+      .accounts({ referrer: referrer.publicKey })
+      .signers([referrer])
+      .rpc();
+
+    // a buyer can't refer themselves
+    await program.methods.registerReferrer().accounts({ referrer: wallet.publicKey }).rpc();
+    await expectAnchorError(buyTicket({ referrer: wallet.publicKey }), "SelfReferral");
+
+    const { potAmount: potBefore, ticketPrice, referralBps } =
+      await program.account.tokenLottery.fetch(tokenLotteryPda);
+    await buyTicket({ referrer: referrer.publicKey });
+
+    // the referrer's share is credited to this round instead of the pot
+    const credit = ticketPrice.muln(referralBps).divn(10_000);
+    const accrual = await program.account.referralAccrual.fetch(referralAccrualPda(referrer.publicKey));
+    expect(accrual.ticketsReferred.toNumber()).to.equal(1);
+    const roundReferral = await program.account.roundReferral.fetch(
+      roundReferralPda(roundId, referrer.publicKey)
+    );
+    expect(roundReferral.roundId.toNumber()).to.equal(roundId.toNumber());
+    expect(roundReferral.referrer.toBase58()).to.equal(referrer.publicKey.toBase58());
+    expect(roundReferral.amount.toString()).to.equal(credit.toString());
+    const { potAmount } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(potAmount.toString()).to.equal(potBefore.add(ticketPrice).sub(credit).toString());
+
+    // credits are paid once the round is drawn and never for a cancelled round
+    const referrerTokenAccount = (
+      await getOrCreateAssociatedTokenAccount(connection, wallet.payer, tokenMint, referrer.publicKey)
+    ).address;
+    const claimReferralRewards = () =>
+      program.methods
+        .claimReferralRewards(roundId)
+        .accounts({
+          referrer: referrer.publicKey,
+          //@ts-ignore
+          referralAccrual: referralAccrualPda(referrer.publicKey),
+          roundReferral: roundReferralPda(roundId, referrer.publicKey),
+          roundHistory: roundHistoryPda(roundId),
+          tokenLottery: tokenLotteryPda,
+          tokenMint,
+          raffleVaultAccount: vaultTokenAccount,
+          referrerTokenAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([referrer])
+        .rpc();
+    await expectAnchorError(claimReferralRewards(), "WinnerNotChosen");
+    await cancelRound(roundId);
+    await expectAnchorError(claimReferralRewards(), "RoundCancelled");
+  });
+
+  it("Only sells tickets to holders of the entry gate token", async () => {
+    const roundId = await openRound(60);
+    const gateMint = await createMint(connection, wallet.payer, wallet.publicKey, null, 0);
+    const setEntryGate = (entryGate: Parameters<typeof program.methods.setEntryGate>[0]) =>
+      program.methods
+        .setEntryGate(entryGate)
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          authority: wallet.publicKey,
+        })
+        .rpc();
+    await setEntryGate({ token: { mint: gateMint, minAmount: new anchor.BN(1) } });
+
+    // no gate token account, or one that doesn't hold enough, is turned away
+    await expectAnchorError(buyTicket(), "EntryGateNotMet");
+    const gateTokenAccount = (
+      await getOrCreateAssociatedTokenAccount(connection, wallet.payer, gateMint, wallet.publicKey)
+    ).address;
+    await expectAnchorError(buyTicket({ gateTokenAccount }), "EntryGateNotMet");
+
+    await mintTo(connection, wallet.payer, gateMint, gateTokenAccount, wallet.payer, 1);
+    await buyTicket({ gateTokenAccount });
+    const { totalTickets } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(totalTickets.toNumber()).to.equal(1);
+
+    await setEntryGate({ none: {} });
+    await cancelRound(roundId);
+  });
+
+  it("Limits presale purchases to the allowlist", async () => {
+    const roundId = await openRound(60);
+    const { startTime, endTime } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+
+    // a single leaf tree, the root is the leaf itself and the proof is empty
+    const allocation = 2;
+    const root = createHash("sha256")
+      .update(Buffer.concat([wallet.publicKey.toBuffer(), new anchor.BN(allocation).toArrayLike(Buffer, "le", 8)]))
+      .digest();
+    const setPresale = (presaleEndTime: anchor.BN) =>
+      program.methods
+        .setPresale(Array.from(root), presaleEndTime)
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          authority: wallet.publicKey,
+        })
+        .rpc();
+
+    // the presale has to end inside the round window
+    await expectAnchorError(setPresale(endTime.addn(1)), "InvalidRoundWindow");
+    const presaleEndTime = startTime.addn(30);
+    await setPresale(presaleEndTime);
+
+    // only the allowlisted allocation gets in, and only up to that allocation
+    await expectAnchorError(buyTicket({ allocation: 1 }), "NotAllowlisted");
+    await buyTicket({ allocation });
+    await buyTicket({ allocation });
+    await expectAnchorError(buyTicket({ allocation }), "WalletTicketLimit");
+
+    // the allowlist can't change once tickets are sold
+    await expectAnchorError(setPresale(presaleEndTime), "ConfigChangeNotAllowed");
+
+    // once the presale ends anyone can buy
+    const wait = presaleEndTime.toNumber() - Math.floor(Date.now() / 1000) + 2;
+    await new Promise((resolve) => setTimeout(resolve, Math.max(wait, 0) * 1000));
+    await buyTicket();
+    const { totalTickets } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(totalTickets.toNumber()).to.equal(3);
+
+    await cancelRound(roundId);
+  });
+
+  it("Pauses ticket sales and claims separately", async () => {
+    const roundId = await openRound(60);
+    const setPaused = (salesPaused: boolean, claimsPaused: boolean, signer = wallet.payer) =>
+      program.methods
+        .setPaused(salesPaused, claimsPaused)
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          signer: signer.publicKey,
+        })
+        .signers([signer])
+        .rpc();
+
+    // only the authority or the pauser can pause
+    await expectAnchorError(setPaused(true, false, anchor.web3.Keypair.generate()), "NotAuthorized");
+
+    await setPaused(true, false);
+    await expectAnchorError(buyTicket(), "LotteryPaused");
+    await setPaused(false, false);
+    await buyTicket();
+
+    // paused claims hold back refunds too
+    await cancelRound(roundId);
+    const refundTicket = () =>
+      program.methods
+        .refundTicket(roundId, new anchor.BN(0))
+        .accounts({
+          holder: wallet.publicKey,
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          roundHistory: roundHistoryPda(roundId),
+          tokenMint,
+          raffleVaultAccount: vaultTokenAccount,
+          holderTokenAccount: userTokenAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+    await setPaused(false, true);
+    await expectAnchorError(refundTicket(), "LotteryPaused");
+    await setPaused(false, false);
+    const before = await tokenBalance(userTokenAccount);
+    await refundTicket();
+    expect(await tokenBalance(userTokenAccount)).to.equal(before + BigInt(10000));
+  });
+
+  it("Holds queued config changes until the timelock passes", async () => {
+    const [pendingConfigChange] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pending_config")],
+      program.programId
+    );
+    const queueConfigChange = (change: Parameters<typeof program.methods.queueConfigChange>[0]) =>
+      program.methods
+        .queueConfigChange(change)
+        .accounts({
+          authority: wallet.publicKey,
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+        })
+        .rpc();
+
+    // delays outside one hour to 30 days are refused up front
+    await expectAnchorError(queueConfigChange({ timelock: [new anchor.BN(60)] }), "InvalidTimelock");
+    await expectAnchorError(
+      queueConfigChange({ timelock: [new anchor.BN(31 * 24 * 60 * 60)] }),
+      "InvalidTimelock"
+    );
+
+    const { ticketPrice, configTimelock } = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    const queuedAt = Math.floor(Date.now() / 1000);
+    await queueConfigChange({ ticketPrice: [ticketPrice.muln(2)] });
+    const pending = await program.account.pendingConfigChange.fetch(pendingConfigChange);
+    expect(pending.proposer.toBase58()).to.equal(wallet.publicKey.toBase58());
+    expect(pending.executableAt.toNumber()).to.be.closeTo(queuedAt + configTimelock.toNumber(), 30);
+
+    // executing before the delay is refused and leaves the config alone
+    await expectAnchorError(
+      program.methods
+        .executeConfigChange()
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          pendingConfigChange,
+          proposer: wallet.publicKey,
+        })
+        .rpc(),
+      "TimelockNotElapsed"
+    );
+    const tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.ticketPrice.toString()).to.equal(ticketPrice.toString());
+
+    // the authority can drop the change, freeing the slot for the next one
+    await program.methods
+      .cancelConfigChange()
+      .accounts({
+        authority: wallet.publicKey,
+        //@ts-ignore
+        tokenLottery: tokenLotteryPda,
+        pendingConfigChange,
+        proposer: wallet.publicKey,
+      })
+      .rpc();
+    expect(await connection.getAccountInfo(pendingConfigChange)).to.equal(null);
+  });
+
+  it("Hands the operator, treasurer and pauser roles to other keys", async () => {
+    const operator = anchor.web3.Keypair.generate();
+    const treasurer = anchor.web3.Keypair.generate();
+    const pauser = anchor.web3.Keypair.generate();
+    const setRoles = (
+      roles: anchor.web3.PublicKey[],
+      authority: anchor.web3.Keypair = wallet.payer
+    ) =>
+      program.methods
+        .setRoles(roles[0], roles[1], roles[2])
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+    const setPaused = (salesPaused: boolean, signer: anchor.web3.Keypair) =>
+      program.methods
+        .setPaused(salesPaused, false)
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          signer: signer.publicKey,
+        })
+        .signers([signer])
+        .rpc();
+
+    // only the authority hands out roles
+    const roles = [operator.publicKey, treasurer.publicKey, pauser.publicKey];
+    await expectAnchorError(setRoles(roles, operator), "NotAuthorized");
+    await setRoles(roles);
+    let tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.operator.toBase58()).to.equal(operator.publicKey.toBase58());
+    expect(tokenLottery.treasurer.toBase58()).to.equal(treasurer.publicKey.toBase58());
+    expect(tokenLottery.pauser.toBase58()).to.equal(pauser.publicKey.toBase58());
+
+    // the pauser can pause, the authority no longer restarts rounds and the operator can't pause
+    await setPaused(true, pauser);
+    tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.salesPaused).to.equal(true);
+    await setPaused(false, pauser);
+    await expectAnchorError(setPaused(true, operator), "NotAuthorized");
+    const now = Math.floor(Date.now() / 1000);
+    await expectAnchorError(
+      program.methods
+        .restartLottery(new anchor.BN(now - 10), new anchor.BN(now + 60), new anchor.BN(10000))
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          operator: wallet.publicKey,
+        })
+        .rpc(),
+      "NotAuthorized"
+    );
+
+    await setRoles([wallet.publicKey, wallet.publicKey, wallet.publicKey]);
+  });
+
+  it("Transfers the authority only once the new key accepts", async () => {
+    const newAuthority = anchor.web3.Keypair.generate();
+    const proposeAuthority = (pending: anchor.web3.PublicKey, authority = wallet.payer) =>
+      program.methods
+        .proposeAuthority(pending)
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+    const acceptAuthority = (signer: anchor.web3.Keypair) =>
+      program.methods
+        .acceptAuthority()
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          newAuthority: signer.publicKey,
+        })
+        .signers([signer])
+        .rpc();
+    const cancelAuthorityTransfer = (authority = wallet.payer) =>
+      program.methods
+        .cancelAuthorityTransfer()
+        .accounts({
+          //@ts-ignore
+          tokenLottery: tokenLotteryPda,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+
+    await expectAnchorError(cancelAuthorityTransfer(), "NoPendingAuthority");
+
+    // a proposal changes nothing until the proposed key accepts it, nobody else can
+    await proposeAuthority(newAuthority.publicKey);
+    let tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.authority.toBase58()).to.equal(wallet.publicKey.toBase58());
+    expect(tokenLottery.pendingAuthority?.toBase58()).to.equal(newAuthority.publicKey.toBase58());
+    await expectAnchorError(acceptAuthority(anchor.web3.Keypair.generate()), "NotAuthorized");
+
+    await acceptAuthority(newAuthority);
+    tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.authority.toBase58()).to.equal(newAuthority.publicKey.toBase58());
+    expect(tokenLottery.pendingAuthority).to.equal(null);
+    await expectAnchorError(proposeAuthority(wallet.publicKey), "NotAuthorized");
+
+    // hand it back, withdrawing a first proposal on the way
+    await proposeAuthority(wallet.publicKey, newAuthority);
+    await cancelAuthorityTransfer(newAuthority);
+    tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.pendingAuthority).to.equal(null);
+    await proposeAuthority(wallet.publicKey, newAuthority);
+    await acceptAuthority(wallet.payer);
+    tokenLottery = await program.account.tokenLottery.fetch(tokenLotteryPda);
+    expect(tokenLottery.authority.toBase58()).to.equal(wallet.publicKey.toBase58());
+  });
+});